@@ -0,0 +1,210 @@
+//! Benchmarks of the performance critical parts on a fixed synthetic image.
+//! Run with `cargo bench`.
+
+use ant_image_seg::image_ants;
+use ant_image_seg::image_arithmetic::{color_distances, segments, ArithmeticImage, Connectivity};
+use ant_image_seg::segment_generation::{self, ContourBorder, SegmentationOptions};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{ImageOutputFormat, Luma, Rgb, RgbImage};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::io::Cursor;
+use std::thread;
+
+const SEED: u64 = 42;
+
+/// A few overlapping shapes with some deterministic noise.
+fn synthetic_image() -> RgbImage {
+    return RgbImage::from_fn(120, 90, |x, y| {
+        let noise = ((x * 7 + y * 13) % 17) as u8;
+        let (dx, dy) = (x as i64 - 45, y as i64 - 45);
+        if dx * dx + dy * dy < 600 {
+            return Rgb([200 - noise, 40 + noise, 40]);
+        }
+        if x > 80 {
+            return Rgb([30, 160 - noise, 60 + noise]);
+        }
+        return Rgb([40 + noise, 40, 200 - noise]);
+    });
+}
+
+/// Pheromones after a few steps of the colony, as a realistic input for segmentation.
+fn trained_pheromones(img: &RgbImage) -> Vec<image_ants::PheromoneImage> {
+    return seeded_pheromones(img, SEED);
+}
+
+fn seeded_pheromones(img: &RgbImage, seed: u64) -> Vec<image_ants::PheromoneImage> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let rules = segment_generation::create_rules(img, Some(1), true, Default::default());
+    let mut pheromones = image_ants::initialize_pheromones(&mut rng, img, &rules);
+    for _ in 0..10 {
+        image_ants::run_colony_step(&mut rng, img, &rules, &mut pheromones);
+    }
+    return pheromones;
+}
+
+fn colony(c: &mut Criterion) {
+    let img = synthetic_image();
+    let mut group = c.benchmark_group("run_colony_step");
+    group.sample_size(20);
+    for multi in [true, false] {
+        let rules = segment_generation::create_rules::<SmallRng>(
+            &img,
+            Some(1),
+            multi,
+            SegmentationOptions::default(),
+        );
+        let name = if multi {
+            "multi objective"
+        } else {
+            "single objective"
+        };
+        group.bench_function(name, |b| {
+            let mut rng = SmallRng::seed_from_u64(SEED);
+            let mut pheromones = image_ants::initialize_pheromones(&mut rng, &img, &rules);
+            b.iter(|| image_ants::run_colony_step(&mut rng, &img, &rules, &mut pheromones));
+        });
+    }
+    group.finish();
+}
+
+fn segmentation(c: &mut Criterion) {
+    let img = synthetic_image();
+    let pheromones = trained_pheromones(&img);
+    let kernel = &SegmentationOptions::default().edge_kernel;
+    let contour = segment_generation::contour_segmententation(
+        &pheromones,
+        0.33,
+        kernel,
+        ContourBorder::Closed,
+    );
+    let (_, segmentation) = segment_generation::region_segmententation(
+        &pheromones,
+        0.33,
+        kernel,
+        ContourBorder::Closed,
+        Connectivity::Four,
+    );
+    c.bench_function("extract_segments", |b| {
+        b.iter(|| segments::extract_segments(black_box(&contour), Connectivity::Four, false))
+    });
+    c.bench_function("edge_value", |b| {
+        b.iter(|| {
+            segments::edge_value(
+                &img,
+                black_box(&segmentation.segments),
+                &color_distances::euclidean,
+                Connectivity::Eight,
+                false,
+            )
+        })
+    });
+}
+
+fn distances(c: &mut Criterion) {
+    let img = synthetic_image();
+    let distances: [(&str, &dyn Fn(&Rgb<u8>, &Rgb<u8>) -> f64); 5] = [
+        ("euclidean_squared", &color_distances::euclidean_squared),
+        ("euclidean", &color_distances::euclidean),
+        ("manhattan", &color_distances::manhattan),
+        ("cosine_unnormed", &color_distances::cosine_unnormed),
+        ("cosine", &color_distances::cosine),
+    ];
+    let mut group = c.benchmark_group("color_distances");
+    for (name, dist) in distances {
+        group.bench_function(name, |b| {
+            b.iter(|| img.pixels().zip(img.pixels().skip(1)).map(|(a, b)| dist(a, b)).sum::<f64>())
+        });
+    }
+    group.finish();
+}
+
+/// The arithmetic of a global update on a large image, once as separate passes
+/// and once with the fused operations.
+fn arithmetic(c: &mut Criterion) {
+    let increase = image_ants::PheromoneImage::from_fn(1000, 1000, |x, y| {
+        return Luma([((x * 7 + y * 13) % 101) as f32]);
+    });
+    let pheromone = image_ants::PheromoneImage::from_pixel(1000, 1000, Luma([0.5]));
+    let mut group = c.benchmark_group("global_update_arithmetic");
+    group.sample_size(20);
+    group.bench_function("separate passes", |b| {
+        b.iter(|| {
+            let (mut increase, mut pheromone) = (increase.clone(), pheromone.clone());
+            increase.clamp(increase.max() * 0.125);
+            increase.normalize();
+            increase.mul_scalar(-1.0);
+            pheromone.add(&increase);
+            pheromone.add_scalar(1.0);
+            pheromone.normalize();
+            pheromone.mul_scalar(2.0);
+            return pheromone;
+        })
+    });
+    group.bench_function("fused passes", |b| {
+        b.iter(|| {
+            let (mut increase, mut pheromone) = (increase.clone(), pheromone.clone());
+            increase.clamp_normalize(0.125);
+            pheromone.add_scaled(&increase, -1.0, 1.0);
+            pheromone.normalize_scaled(2.0);
+            return pheromone;
+        })
+    });
+    group.finish();
+}
+
+/// The three segmentation outputs of a solution, encoded as PNG in memory.
+fn front_output(img: &RgbImage, pheromones: &[image_ants::PheromoneImage]) -> usize {
+    let options = SegmentationOptions::default();
+    let contour = options.region_contour(img, pheromones, 0.33);
+    let overlayed = segment_generation::overlay_contour(img, &contour, Rgb([255, 0, 0]), 255);
+    let (segmented, _) = segment_generation::colorized_region_segmententation(
+        img,
+        pheromones,
+        0.33,
+        &options,
+        segment_generation::SegmentColoring::Mean,
+        1.0,
+    );
+    let mut size = 0;
+    for output in [contour, overlayed, segmented] {
+        let mut encoded = Cursor::new(vec![]);
+        output.write_to(&mut encoded, ImageOutputFormat::Png).unwrap();
+        size += encoded.into_inner().len();
+    }
+    return size;
+}
+
+/// Outputs of a front of 32 solutions, once one after another
+/// and once split into chunks over all available threads, like the binary does.
+fn front_outputs(c: &mut Criterion) {
+    let img = synthetic_image();
+    let front: Vec<_> = (0..32).map(|seed| seeded_pheromones(&img, seed)).collect();
+    let threads = thread::available_parallelism().map_or(1, |x| x.get());
+    let mut group = c.benchmark_group("front_outputs");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| front.iter().map(|pheromones| front_output(&img, pheromones)).sum::<usize>())
+    });
+    group.bench_function(format!("{} threads", threads), |b| {
+        b.iter(|| {
+            let chunk_size = (front.len() + threads - 1) / threads;
+            return thread::scope(|scope| {
+                let handles: Vec<_> = front
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        let img = &img;
+                        return scope.spawn(move || {
+                            return chunk.iter().map(|p| front_output(img, p)).sum::<usize>();
+                        });
+                    })
+                    .collect();
+                return handles.into_iter().map(|handle| handle.join().unwrap()).sum::<usize>();
+            });
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, colony, segmentation, distances, arithmetic, front_outputs);
+criterion_main!(benches);
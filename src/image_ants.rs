@@ -1,74 +1,126 @@
 //! Core functionality for ant colony algorithms on images.
 
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::image_arithmetic::color_distances;
-use super::image_arithmetic::{generate_color, ArithmeticImage, Point};
-use image::{DynamicImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+use super::image_arithmetic::{generate_color, text, ArithmeticImage, Connectivity, Point};
+use image::{
+    imageops, DynamicImage, ImageBuffer, Luma, Pixel, Rgb, Rgb32FImage, RgbImage, Rgba, RgbaImage,
+};
+use log::{debug, warn};
 use rand;
-use rand::seq::SliceRandom;
+use rand::distributions::{Distribution, WeightedError, WeightedIndex};
+use rand::seq::{IteratorRandom, SliceRandom};
 use rand::SeedableRng;
 
 pub type PheromoneImage = ImageBuffer<Luma<f32>, Vec<f32>>;
 
-impl ArithmeticImage<f32> for PheromoneImage {
-    fn max(&self) -> f32 {
-        return self.as_raw().iter().fold(0.0, |a: f32, &b| a.max(b));
-    }
+/// Summary of a pheromone channel, to follow how its trails sharpen over time.
+#[derive(Debug, Clone, Copy)]
+pub struct PheromoneStatistics {
+    pub max: f32,
+    pub mean: f32,
+    /// Fraction of the pixels holding any pheromone.
+    pub nonzero_fraction: f32,
+    /// See `ArithmeticImage::entropy`.
+    pub entropy: f32,
+}
 
-    fn min(&self) -> f32 {
-        return self.as_raw().iter().fold(f32::INFINITY, |a: f32, &b| a.min(b));
-    }
+impl PheromoneStatistics {
+    pub const CSV_HEADER: &'static str = "max,mean,nonzero_fraction,entropy";
 
-    fn normalize(&mut self) {
-        let max = self.max();
-        if max != 0.0 && max != 1.0 {
-            for pixel in self.pixels_mut() {
-                (pixel.0)[0] /= max;
-            }
-        }
+    pub fn new(pheromone: &PheromoneImage) -> Self {
+        let nonzero = pheromone.as_raw().iter().filter(|&&value| value > 0.0).count();
+        return Self {
+            max: pheromone.max(),
+            mean: pheromone.mean(),
+            nonzero_fraction: nonzero as f32 / pheromone.as_raw().len().max(1) as f32,
+            entropy: pheromone.entropy(),
+        };
     }
 
-    fn binarize(&mut self, threshold: f32) {
-        self.normalize();
-        for pixel in self.pixels_mut() {
-            (pixel.0)[0] = ((pixel.0)[0] > threshold) as u8 as f32;
-        }
+    /// Values in the order of `CSV_HEADER`.
+    pub fn csv_row(&self) -> String {
+        return format!("{},{},{},{}", self.max, self.mean, self.nonzero_fraction, self.entropy);
     }
+}
 
-    fn clamp(&mut self, threshold: f32) {
-        for pixel in self.pixels_mut() {
-            (pixel.0)[0] = threshold.min((pixel.0)[0]);
-        }
-    }
+/// Neighbours an ant chooses its next step from, weighted by their attractiveness.
+/// If none of the candidates has any weight, the ant steps to any neighbour within the image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CandidateList {
+    /// All neighbours.
+    All,
+    /// Only the given amount of most attractive neighbours, so ants follow trails more greedily.
+    Strongest(usize),
+    /// A random subset of the given size among the reachable neighbours,
+    /// so ants explore more.
+    Random(usize),
+}
 
-    fn add(&mut self, other: &Self) {
-        for (x, y, pixel) in self.enumerate_pixels_mut() {
-            (pixel.0)[0] += (other.get_pixel(x, y).0)[0];
-        }
-    }
+/// Constants of the heuristic by which ants weigh their next step, see `Ant::step`.
+/// Like in classic ant colony optimization, the weight is the product of the trail `τ`,
+/// i.e. the base weight plus the pheromones, and the heuristic `η`,
+/// i.e. the target term divided by the color term, each raised to a power: `τ^α · η^β`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AntHeuristicParams {
+    /// Exponent of the trail, higher values make ants follow strong trails more strictly,
+    /// 0 makes them ignore pheromones altogether.
+    pub alpha: f32,
+    /// Exponent of the heuristic, higher values make ants head for their target
+    /// and avoid color changes more strictly, 0 makes them only follow pheromones.
+    pub beta: f32,
+    /// Weight of each neighbour before its pheromones are added,
+    /// so that ants also step onto pixels without any pheromones.
+    pub base_weight: f32,
+    /// Added to how much closer a neighbour is to the target, by which the weight is multiplied.
+    /// Higher values make ants care less about their target.
+    /// Must exceed the longest step, i.e. the square root of 2, to keep weights positive.
+    pub target_bias: f32,
+    /// Added to the color distance to a neighbour, by which the weight is divided.
+    /// Higher values make ants care less about color changes along their walk.
+    pub color_divisor: f32,
+    /// Fraction of the weight taken from pixels the ant visited before,
+    /// which wears off with `AntColonyRules::revisit_decay`.
+    /// At 1, ants do not step onto pixels they just visited, unless all neighbours are such pixels.
+    pub revisit_penalty: f32,
+}
 
-    fn add_scalar(&mut self, num: f32) {
-        for pixel in self.pixels_mut() {
-            (pixel.0)[0] += num;
-            if (pixel.0)[0] < 0.0 {
-                (pixel.0)[0] = 0.0;
-            }
+impl AntHeuristicParams {
+    /// Rejects constants for which weights are not positive.
+    pub fn check(&self) -> Result<(), RulesError> {
+        if self.target_bias.is_nan() || self.target_bias <= std::f32::consts::SQRT_2 {
+            return Err(RulesError::TargetBias);
         }
+        return Ok(());
     }
+}
 
-    fn mul(&mut self, other: &Self) {
-        for (x, y, pixel) in self.enumerate_pixels_mut() {
-            (pixel.0)[0] *= (other.get_pixel(x, y).0)[0];
-        }
+impl Default for AntHeuristicParams {
+    fn default() -> Self {
+        return Self {
+            alpha: 1.0,
+            beta: 1.0,
+            base_weight: 0.1,
+            target_bias: 3.0,
+            color_divisor: 128.0,
+            revisit_penalty: 0.99,
+        };
     }
+}
 
-    fn mul_scalar(&mut self, num: f32) {
-        for pixel in self.pixels_mut() {
-            (pixel.0)[0] *= num;
-        }
+/// Raises the base to the exponent, skipping the costly power for the default exponent of 1.
+fn power(base: f32, exponent: f32) -> f32 {
+    if exponent == 1.0 {
+        return base;
     }
+    return base.powf(exponent);
 }
 
 pub type UpdateFunction<R> =
@@ -76,11 +128,75 @@ pub type UpdateFunction<R> =
 pub type GlobalUpdateFunction<R> =
     dyn Fn(&mut R, &RgbImage, &mut [PheromoneImage], &HashSet<Point>) + Send + Sync;
 
+/// Reasons the pheromone functions given to `AntColonyRules::new`
+/// or the constants checked by `AntHeuristicParams::check` are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulesError {
+    /// No functions were given, so there are no pheromone channels.
+    NoPheromones,
+    /// More than the initialization and local update functions were given.
+    ExtraPheromoneFunctions,
+    /// The functions were given for differing amounts of pheromone channels.
+    UnequalPheromoneFunctions,
+    /// The target bias does not exceed the longest step.
+    TargetBias,
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.write_str(match self {
+            Self::NoPheromones => "no pheromones",
+            Self::ExtraPheromoneFunctions => "extra pheromone functions",
+            Self::UnequalPheromoneFunctions => "unequal amount of pheromone functions",
+            Self::TargetBias => "target bias of at most the longest step",
+        });
+    }
+}
+
+impl Error for RulesError {}
+
 pub struct AntColonyRules<CR: rand::Rng> {
     pub max_ant_steps: usize,
     pub ants_per_global_update: usize,
+    /// Whether ants walk back to their spawn point after reaching their target.
+    /// The way back is a walk of its own, which deposits pheromones again, so returning ants
+    /// reinforce paths between the two points more strongly than one-way walks,
+    /// at the cost of spending more of their `max_ant_steps`.
     pub ants_return: bool,
     pub parallelity: usize,
+    /// Factor by which the penalty for revisiting a pixel decreases with each step since.
+    /// 1 keeps the penalty constant, smaller values let ants re-cross older paths.
+    pub revisit_decay: f32,
+    /// Constants of the weights of the next step.
+    pub heuristic: AntHeuristicParams,
+    /// Maximum distance along each axis between an ant's spawn point and its target.
+    /// Shorter walks are cheaper, so more ants are needed to cover the image,
+    /// but each of them only explores its local neighbourhood.
+    /// If none, targets are chosen anywhere in the image.
+    pub max_target_distance: Option<u32>,
+    /// Combine results of parallel ants in a fixed order, so results only depend on the seed.
+    pub deterministic: bool,
+    /// Let ants walk across the edges of the image onto the opposite side, like on a torus.
+    /// Distances to their targets take the shorter way around as well.
+    pub wrap: bool,
+    /// Neighbours ants can move to in each step.
+    pub connectivity: Connectivity,
+    /// Which of these neighbours are candidates for the next step.
+    /// Lists need to hold at least one candidate.
+    pub candidate_list: CandidateList,
+    /// Weights of the color channels when comparing colors along the walk,
+    /// using `color_distances::weighted_euclidean` instead of the manhattan distance.
+    pub channel_distance_weights: Option<[f64; 3]>,
+    /// Amount of steps after which ants stop if they did not get closer to their target.
+    /// If none, ants only stop after `max_ant_steps`.
+    pub stall_window: Option<usize>,
+    /// Wall-clock time after which each ant stops, so that a single slow ant cannot
+    /// hold up the others of a parallel batch. Results then depend on the speed of the machine,
+    /// so they are no longer reproducible from the seed alone.
+    /// If none, ants only stop after `max_ant_steps`.
+    pub ant_time_budget: Option<Duration>,
+    /// Where ants are spawned, their targets are always chosen like in `Ant::spawn`.
+    pub spawn_strategy: SpawnStrategy,
     pub initialization_funcs: Vec<Option<Box<UpdateFunction<CR>>>>,
     pub local_update_funcs: Vec<Option<Box<UpdateFunction<CR>>>>,
     pub global_update_func: Option<Box<GlobalUpdateFunction<CR>>>,
@@ -92,19 +208,19 @@ impl<CR: rand::Rng> AntColonyRules<CR> {
         parallelity: Option<usize>,
         mut pheromone_functions: Vec<Vec<Option<Box<UpdateFunction<CR>>>>>,
         global_update_func: Option<Box<GlobalUpdateFunction<CR>>>,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, RulesError> {
         let mut pheromone_channels = 0;
         if pheromone_functions.len() > 0 {
             pheromone_channels = pheromone_functions[0].len();
         }
-        if pheromone_channels <= 0 {
-            return Err("no pheromones");
+        if pheromone_channels == 0 {
+            return Err(RulesError::NoPheromones);
         }
         if pheromone_functions.len() > 2 {
-            return Err("extra pheromone functions");
+            return Err(RulesError::ExtraPheromoneFunctions);
         }
         if pheromone_functions.iter().any(|x| x.len() != pheromone_channels) {
-            return Err("unequal amount of pheromone functions");
+            return Err(RulesError::UnequalPheromoneFunctions);
         }
         while pheromone_functions.len() < 2 {
             let mut substitute = vec![];
@@ -113,17 +229,36 @@ impl<CR: rand::Rng> AntColonyRules<CR> {
             }
             pheromone_functions.insert(0, substitute);
         }
-        let mut parallelity =
-            parallelity.unwrap_or(thread::available_parallelism().map_or(1, |x| x.get()));
-        if parallelity > ants_per_global_update {
-            parallelity = 1;
+        // More threads than ants would leave some threads without work.
+        let max_parallelity = ants_per_global_update.max(1);
+        if parallelity.map_or(false, |p| p > max_parallelity) {
+            warn!(
+                "Parallelity of {} exceeds the {} ants per global update, using {} threads.",
+                parallelity.unwrap(),
+                ants_per_global_update,
+                max_parallelity
+            );
         }
+        let parallelity = parallelity
+            .unwrap_or(thread::available_parallelism().map_or(1, |x| x.get()))
+            .min(max_parallelity);
 
         return Ok(Self {
             max_ant_steps,
             ants_per_global_update,
             ants_return,
             parallelity,
+            revisit_decay: 1.0,
+            heuristic: AntHeuristicParams::default(),
+            max_target_distance: None,
+            deterministic: false,
+            wrap: false,
+            connectivity: Connectivity::Eight,
+            candidate_list: CandidateList::All,
+            channel_distance_weights: None,
+            stall_window: None,
+            ant_time_budget: None,
+            spawn_strategy: SpawnStrategy::Uniform,
             global_update_func,
             local_update_funcs: pheromone_functions.pop().unwrap(),
             initialization_funcs: pheromone_functions.pop().unwrap(),
@@ -171,72 +306,276 @@ impl<CR: rand::Rng> AntColonyRules<CR> {
     }
 }
 
+/// Distribution of locations over the pixels of an image.
+#[derive(Debug, Clone)]
+pub struct SpawnMap {
+    width: u32,
+    distribution: WeightedIndex<f32>,
+}
+
+impl SpawnMap {
+    /// Locations are chosen with probability proportional to the given weights.
+    pub fn new(weights: &PheromoneImage) -> Result<Self, WeightedError> {
+        return Ok(Self {
+            width: weights.width(),
+            distribution: WeightedIndex::new(weights.as_raw())?,
+        });
+    }
+
+    pub fn sample<R: rand::Rng>(&self, rng: &mut R) -> Point {
+        let index = self.distribution.sample(rng) as u32;
+        return Point::from((index % self.width, index / self.width));
+    }
+}
+
+/// Locations ants are spawned at, see `Ant::spawn_with_rules`.
+#[derive(Debug, Clone)]
+pub enum SpawnStrategy {
+    /// Uniformly at random.
+    Uniform,
+    /// Drawn from the map, which is built once, e.g. from the color gradient magnitude
+    /// of `segment_generation::saliency_map`, so that ants start near edges.
+    GradientWeighted(SpawnMap),
+    /// At the given points in a round-robin fashion.
+    /// Without any points, ants are spawned uniformly instead.
+    FromSet(Vec<Point>),
+}
+
 #[derive(Debug)]
 pub struct Ant {
     pub position: Point,
     pub target: Point,
     pub visited: HashSet<Point>,
+    /// Step at which each visited pixel was last visited.
+    pub last_visits: HashMap<Point, usize>,
+    /// Steps taken so far.
+    pub steps: usize,
+    /// Location the ant returns to after reaching its target, if `ants_return` is set.
+    /// None once the ant is on its way back.
+    pub start: Option<Point>,
+    /// Positions of the ant in the order they were visited, only recorded if some.
+    /// Unlike `visited`, this grows with every step, so it is not recorded by default.
+    pub path: Option<Vec<Point>>,
+    /// Distances to the target within the stall window.
+    recent_distances: VecDeque<f64>,
+    stopped: bool,
 }
 
 impl Ant {
-    pub fn spawn<R: rand::Rng>(rng: &mut R, width: u32, height: u32) -> Self {
+    /// Spawns the ant uniformly with a uniform target.
+    /// If given, the target lies at most `max_target_distance` pixels away along each axis.
+    pub fn spawn<R: rand::Rng>(
+        rng: &mut R, width: u32, height: u32, max_target_distance: Option<u32>,
+    ) -> Self {
+        let position = Point::spawn(rng, width, height);
+        return Self::spawn_at(rng, position, width, height, max_target_distance);
+    }
+
+    /// The target is redrawn until it differs from the position,
+    /// as the ant would stop right away otherwise,
+    /// unless the image or the target distance leave no other choice.
+    pub fn spawn_at<R: rand::Rng>(
+        rng: &mut R, position: Point, width: u32, height: u32, max_target_distance: Option<u32>,
+    ) -> Self {
+        let can_move = width * height > 1 && max_target_distance != Some(0);
+        let target = loop {
+            let target = match max_target_distance {
+                Some(distance) => Point::spawn_near(rng, position, distance, width, height),
+                None => Point::spawn(rng, width, height),
+            };
+            if target != position || !can_move {
+                break target;
+            }
+        };
         return Self {
-            position: Point::spawn(rng, width, height),
-            target: Point::spawn(rng, width, height),
+            position,
+            target,
             visited: HashSet::new(),
+            last_visits: HashMap::new(),
+            steps: 0,
+            start: Some(position),
+            path: None,
+            recent_distances: VecDeque::new(),
+            stopped: false,
         };
     }
 
+    /// Spawns the ant like the rules would spawn the ant with the given index within a step,
+    /// see `AntColonyRules::spawn_strategy`.
+    pub fn spawn_with_rules<R: rand::Rng, CR: rand::Rng>(
+        rng: &mut R, img: &RgbImage, rules: &AntColonyRules<CR>, index: usize,
+    ) -> Self {
+        let (width, height) = img.dimensions();
+        return match &rules.spawn_strategy {
+            SpawnStrategy::GradientWeighted(spawn_map) => {
+                Self::spawn_weighted(rng, spawn_map, width, height, rules.max_target_distance)
+            }
+            SpawnStrategy::FromSet(points) if !points.is_empty() => {
+                let position = points[index % points.len()];
+                Self::spawn_at(rng, position, width, height, rules.max_target_distance)
+            }
+            _ => Self::spawn(rng, width, height, rules.max_target_distance),
+        };
+    }
+
+    /// Spawns the ant at a location drawn from the given map,
+    /// the target is chosen like for `spawn`, regardless of the map.
+    pub fn spawn_weighted<R: rand::Rng>(
+        rng: &mut R, spawn_map: &SpawnMap, width: u32, height: u32,
+        max_target_distance: Option<u32>,
+    ) -> Self {
+        let position = spawn_map.sample(rng);
+        return Self::spawn_at(rng, position, width, height, max_target_distance);
+    }
+
+    /// Walks until the ant is no longer active, see `step`,
+    /// or until it used up `AntColonyRules::ant_time_budget`.
     pub fn run<R: rand::Rng, CR: rand::Rng>(
         &mut self, rng: &mut R, img: &RgbImage, rules: &AntColonyRules<CR>,
         pheromones: &[PheromoneImage],
     ) {
+        let start_time = Instant::now();
+        while self.step(rng, img, rules, pheromones) {
+            if rules.ant_time_budget.map_or(false, |budget| start_time.elapsed() >= budget) {
+                self.stop();
+                return;
+            }
+        }
+    }
+
+    /// Advances the ant by a single step and returns whether it is still active.
+    /// Ants stop once they reached their target, possibly after returning to their start,
+    /// once they stalled or after `max_ant_steps`. Stopped ants do not move any further.
+    pub fn step<R: rand::Rng, CR: rand::Rng>(
+        &mut self, rng: &mut R, img: &RgbImage, rules: &AntColonyRules<CR>,
+        pheromones: &[PheromoneImage],
+    ) -> bool {
+        if self.stopped {
+            return false;
+        }
+        if self.steps >= rules.max_ant_steps {
+            return self.stop();
+        }
         let corner_a = Point { x: 0, y: 0 };
         let corner_b = Point { x: (img.width() - 1) as i64, y: (img.height() - 1) as i64 };
-        let mut start = Some(self.position);
-        for _ in 0..rules.max_ant_steps {
-            if self.position == self.target {
-                if rules.ants_return && start != None {
-                    self.target = start.unwrap();
-                    start = None;
-                } else {
-                    break;
-                }
+        let distance = |a: &Point, b: &Point| -> f64 {
+            if rules.wrap {
+                return a.wrapped_euclidean_distance(b, img.width(), img.height());
             }
-            self.visited.insert(self.position);
-            let dist = self.target.euclidean_distance(&self.position);
-            let get_weight = |newpos: &Point| -> f32 {
-                if !newpos.is_within_rectangle(&corner_a, &corner_b) {
-                    return 0.0;
-                }
-                let mut weight = 0.1;
-                // Follow pheromones.
-                for pheromone in pheromones {
-                    let strength = newpos.get_pixel(pheromone).0[0];
-                    if strength > 0.0 {
-                        weight += strength;
-                    }
+            return a.euclidean_distance(b);
+        };
+        if self.position == self.target {
+            match self.start.take() {
+                Some(start) if rules.ants_return => {
+                    self.target = start;
+                    self.recent_distances.clear();
                 }
-                // Higher probability to walk towards target.
-                weight *= ((dist - self.target.euclidean_distance(&newpos)) as f32) + 3.0;
-                // Walk along paths of similar color.
-                let cdist =
-                    color_distances::manhattan(self.position.get_pixel(img), newpos.get_pixel(img));
-                weight /= 128.0 + cdist as f32;
-                // Lower probability to visit pixel more than once.
-                if self.visited.contains(&newpos) {
-                    weight *= 0.01;
+                _ => return self.stop(),
+            }
+        }
+        let step = self.steps;
+        self.visited.insert(self.position);
+        if let Some(path) = &mut self.path {
+            path.push(self.position);
+        }
+        self.last_visits.insert(self.position, step);
+        let dist = distance(&self.target, &self.position);
+        // Stop ants that have not come closer to their target within the window.
+        if let Some(window) = rules.stall_window {
+            self.recent_distances.push_back(dist);
+            if self.recent_distances.len() > window
+                && dist >= self.recent_distances.pop_front().unwrap()
+            {
+                return self.stop();
+            }
+        }
+        let get_weight = |newpos: &Point| -> f32 {
+            if !newpos.is_within_rectangle(&corner_a, &corner_b) {
+                return 0.0;
+            }
+            let params = &rules.heuristic;
+            // Follow pheromones.
+            let mut trail = params.base_weight;
+            for pheromone in pheromones {
+                let strength = newpos.get_pixel(pheromone).0[0];
+                if strength > 0.0 {
+                    trail += strength;
                 }
-                return weight;
+            }
+            let mut weight = power(trail, params.alpha);
+            // Higher probability to walk towards target.
+            let approach = ((dist - distance(&self.target, newpos)) as f32) + params.target_bias;
+            weight *= power(approach, params.beta);
+            // Walk along paths of similar color.
+            let (current, next) = (self.position.get_pixel(img), newpos.get_pixel(img));
+            let cdist = match &rules.channel_distance_weights {
+                Some(weights) => color_distances::weighted_euclidean(current, next, weights),
+                None => color_distances::manhattan(current, next),
             };
-            self.position = *self
-                .position
-                .iterate_neighbourhood()
-                .collect::<Vec<Point>>()
-                .choose_weighted(rng, get_weight)
-                .unwrap();
+            weight /= power(params.color_divisor + cdist as f32, params.beta);
+            // Lower probability to visit pixel more than once.
+            // The penalty wears off for pixels visited longer ago, if decay is enabled.
+            if let Some(last_visit) = self.last_visits.get(&newpos) {
+                let age = (step - last_visit) as i32;
+                weight *= 1.0 - params.revisit_penalty * rules.revisit_decay.powi(age);
+            }
+            return weight;
+        };
+        let neighbours = self
+            .position
+            .iterate_connected(rules.connectivity)
+            .map(|newpos| {
+                if rules.wrap {
+                    newpos.wrap(img.width(), img.height())
+                } else {
+                    newpos
+                }
+            })
+            .collect::<Vec<Point>>();
+        let mut candidates = neighbours
+            .iter()
+            .map(|&newpos| (newpos, get_weight(&newpos)))
+            .collect::<Vec<(Point, f32)>>();
+        match rules.candidate_list {
+            CandidateList::All => {}
+            CandidateList::Strongest(amount) => {
+                candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+                candidates.truncate(amount);
+            }
+            CandidateList::Random(amount) => {
+                // Neighbours outside of the image must not take the place of reachable ones.
+                candidates.retain(|candidate| candidate.1 > 0.0);
+                candidates = candidates.choose_multiple(rng, amount).copied().collect();
+            }
         }
+        self.position = match candidates.choose_weighted(rng, |candidate| candidate.1) {
+            Ok(candidate) => candidate.0,
+            // No weights are left, e.g. if all neighbours were just visited at a penalty of 1,
+            // so any neighbour within the image will do.
+            Err(_) => {
+                let reachable = neighbours
+                    .into_iter()
+                    .filter(|newpos| newpos.is_within_rectangle(&corner_a, &corner_b))
+                    .choose(rng);
+                match reachable {
+                    Some(newpos) => newpos,
+                    None => return self.stop(),
+                }
+            }
+        };
+        self.steps += 1;
+        return true;
+    }
+
+    fn stop(&mut self) -> bool {
         self.visited.insert(self.position);
+        if let Some(path) = &mut self.path {
+            if path.last() != Some(&self.position) {
+                path.push(self.position);
+            }
+        }
+        self.stopped = true;
+        return false;
     }
 }
 
@@ -246,17 +585,52 @@ pub fn initialize_pheromones<CR: rand::Rng>(
     return rules.initialize_pheromones(rng, img);
 }
 
+/// Resizes the pheromones, e.g. to continue at full resolution after a run on a smaller image.
+/// Trails are interpolated, so they become blurrier the more they are scaled up.
+pub fn resize_pheromones(
+    pheromones: &[PheromoneImage], width: u32, height: u32,
+) -> Vec<PheromoneImage> {
+    return pheromones
+        .iter()
+        .map(|pheromone| imageops::resize(pheromone, width, height, imageops::FilterType::Triangle))
+        .collect();
+}
+
+/// Hash of the dimensions and values of the pheromones, stable across runs.
+/// Pheromones changed in place get a different fingerprint, unlike their address.
+pub fn pheromone_fingerprint(pheromones: &[PheromoneImage]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for pheromone in pheromones {
+        pheromone.dimensions().hash(&mut hasher);
+        pheromone.as_raw().iter().for_each(|value| value.to_bits().hash(&mut hasher));
+    }
+    return hasher.finish();
+}
+
+/// Mixes the other pheromones into the pheromones, channel by channel.
+/// A weight of 0 keeps the pheromones, 1 replaces them with the other ones.
+pub fn mix_pheromones(pheromones: &mut [PheromoneImage], other: &[PheromoneImage], weight: f32) {
+    for (pheromone, other) in pheromones.iter_mut().zip(other) {
+        let mut other = other.clone();
+        other.mul_scalar(weight);
+        pheromone.mul_scalar(1.0 - weight);
+        pheromone.add(&other);
+    }
+}
+
 /// Thread-safe run of multiple ants.
 /// Updates pheromones after each ant according to local rules.
 /// Returns the pixels visited by each ant.
+/// `first_ant` is the index of the first ant within the colony step,
+/// used to assign spawn points consistently across threads.
 pub fn create_and_run_ants<CR: rand::Rng>(
     rng: &mut CR, img: &RgbImage, rules: &AntColonyRules<CR>, pheromones: &[PheromoneImage],
-    number_of_ants: usize,
+    first_ant: usize, number_of_ants: usize,
 ) -> (Vec<PheromoneImage>, Vec<HashSet<Point>>) {
     let mut visited_sets = vec![];
     let mut pheromones_mut = pheromones.to_vec();
-    for _ in 0..number_of_ants {
-        let mut ant = Ant::spawn(rng, img.width(), img.height());
+    for i in first_ant..first_ant + number_of_ants {
+        let mut ant = Ant::spawn_with_rules(rng, img, rules, i);
         ant.run(rng, img, rules, &mut pheromones_mut);
         rules.local_update(rng, img, &mut pheromones_mut, &ant.visited);
         visited_sets.push(ant.visited);
@@ -264,6 +638,22 @@ pub fn create_and_run_ants<CR: rand::Rng>(
     return (pheromones_mut, visited_sets);
 }
 
+/// Paths of ants walking on the given pheromones, in the order of their steps.
+/// The pheromones are not updated, so the ants walk independently of each other.
+pub fn sample_trajectories<R: rand::Rng, CR: rand::Rng>(
+    rng: &mut R, img: &RgbImage, rules: &AntColonyRules<CR>, pheromones: &[PheromoneImage],
+    number_of_ants: usize,
+) -> Vec<Vec<Point>> {
+    return (0..number_of_ants)
+        .map(|i| {
+            let mut ant = Ant::spawn_with_rules(rng, img, rules, i);
+            ant.path = Some(vec![]);
+            ant.run(rng, img, rules, pheromones);
+            return ant.path.unwrap();
+        })
+        .collect();
+}
+
 /// Run multiple ants in parallel.
 /// Collects their pheromones to perform a global update afterwards.
 pub fn run_colony_step<CR: rand::Rng + SeedableRng + Send>(
@@ -271,25 +661,30 @@ pub fn run_colony_step<CR: rand::Rng + SeedableRng + Send>(
 ) {
     let mut total_visited = HashSet::new();
     thread::scope(|scope| {
-        let mut ants_left = rules.ants_per_global_update;
         let mut threads = vec![];
+        // Spread the remainder over the first threads, so no thread gets more than one extra ant.
+        let ants_per_thread = rules.ants_per_global_update / rules.parallelity;
+        let remainder = rules.ants_per_global_update % rules.parallelity;
+        let mut first_ant = 0;
         for i in 0..rules.parallelity {
             let pheromones = pheromones.to_vec();
-            let mut ants = ants_left;
-            if i < rules.parallelity - 1 {
-                ants = ants.min(rules.ants_per_global_update / rules.parallelity);
-            }
-            ants_left -= ants;
+            let ants = ants_per_thread + (i < remainder) as usize;
             let mut thread_rng = CR::from_rng(&mut *rng).unwrap();
             threads.push(scope.spawn(move || {
-                create_and_run_ants(&mut thread_rng, &img, rules, &pheromones, ants)
+                create_and_run_ants(&mut thread_rng, &img, rules, &pheromones, first_ant, ants)
             }));
+            first_ant += ants;
         }
         while !threads.is_empty() {
             thread::yield_now();
             // Find available threads to join.
-            let (finished, unfinished): (Vec<_>, Vec<_>) =
-                threads.into_iter().partition(|join_handle| join_handle.is_finished());
+            let (finished, unfinished): (Vec<_>, Vec<_>) = if rules.deterministic {
+                // Floating point addition is not associative, so combine in a fixed order.
+                let unfinished = threads.split_off(1);
+                (threads, unfinished)
+            } else {
+                threads.into_iter().partition(|join_handle| join_handle.is_finished())
+            };
             // Combine pheromones and visited pixels.
             for join_handle in finished.into_iter() {
                 let (part_pheromones, part_visited_sets) = join_handle.join().unwrap();
@@ -302,8 +697,24 @@ pub fn run_colony_step<CR: rand::Rng + SeedableRng + Send>(
             threads = unfinished;
         }
     });
+    debug!(
+        "Colony step finished, {} ants visited {} pixels.",
+        rules.ants_per_global_update,
+        total_visited.len()
+    );
     // Finished combining partial results, can run global rules now.
+    let start_time = Instant::now();
     rules.global_update(rng, img, pheromones, &total_visited);
+    debug!("Global update took {:?}.", start_time.elapsed());
+}
+
+/// Converts the pheromone to a float image without any normalization,
+/// replicating its values in every channel.
+/// Encoders like OpenEXR only support color float images.
+pub fn raw_pheromone(pheromone: &PheromoneImage) -> Rgb32FImage {
+    return Rgb32FImage::from_fn(pheromone.width(), pheromone.height(), |x, y| {
+        pheromone.get_pixel(x, y).to_rgb()
+    });
 }
 
 pub fn colorize_pheromone(pheromone: &PheromoneImage, color: Rgb<u8>, max_alpha: u8) -> RgbaImage {
@@ -319,7 +730,8 @@ pub fn colorize_pheromone(pheromone: &PheromoneImage, color: Rgb<u8>, max_alpha:
     });
 }
 
-pub fn visualize_pheromones(pheromones: &[PheromoneImage]) -> RgbImage {
+/// Blends the colorized pheromones on top of each other onto the given background.
+pub fn blend_pheromones(pheromones: &[PheromoneImage], background: Rgba<u8>) -> RgbaImage {
     let peaks: Vec<_> = pheromones.iter().map(|p| p.max()).collect();
     let total: f32 = peaks.iter().sum();
     let intensities: Vec<_> = peaks.iter().map(|x| x / total).collect();
@@ -329,12 +741,267 @@ pub fn visualize_pheromones(pheromones: &[PheromoneImage]) -> RgbImage {
         .enumerate()
         .map(|(i, p)| colorize_pheromone(&p, generate_color(i), (255.0 * intensities[i]) as u8))
         .collect();
-    let result = RgbaImage::from_fn(pheromones[0].width(), pheromones[0].height(), |x, y| {
-        let mut pixel = Rgba([0, 0, 0, 255]);
+    return RgbaImage::from_fn(pheromones[0].width(), pheromones[0].height(), |x, y| {
+        let mut pixel = background;
         for pheromone in &colorized_pheromones {
             pixel.blend(pheromone.get_pixel(x, y));
         }
         pixel
     });
+}
+
+pub fn visualize_pheromones(pheromones: &[PheromoneImage]) -> RgbImage {
+    let result = blend_pheromones(pheromones, Rgba([0, 0, 0, 255]));
     return DynamicImage::from(result).to_rgb8();
 }
+
+/// Like `visualize_pheromones`, but areas without pheromones stay transparent,
+/// so the result can be overlaid on the original image.
+pub fn visualize_pheromones_transparent(pheromones: &[PheromoneImage]) -> RgbaImage {
+    return blend_pheromones(pheromones, Rgba([0, 0, 0, 0]));
+}
+
+/// Overlays the summed pheromones onto the image, so strong trails glow in the given color.
+pub fn heatmap_overlay(
+    img: &RgbImage, pheromones: &[PheromoneImage], color: Rgb<u8>, max_alpha: u8,
+) -> RgbImage {
+    let mut summed = pheromones[0].clone();
+    for pheromone in &pheromones[1..] {
+        summed.add(pheromone);
+    }
+    let heatmap = colorize_pheromone(&summed, color, max_alpha);
+    let mut canvas = DynamicImage::from(img.clone());
+    imageops::overlay(&mut canvas, &heatmap, 0, 0);
+    return canvas.to_rgb8();
+}
+
+/// Draws the paths onto a darkened copy of the image, each in its own color.
+/// Starting points are marked in white.
+pub fn draw_trajectories(img: &RgbImage, trajectories: &[Vec<Point>]) -> RgbImage {
+    let mut canvas = imageops::colorops::brighten(img, -96);
+    for (i, path) in trajectories.iter().enumerate() {
+        let color = generate_color(i);
+        for point in path {
+            point.put_pixel(&mut canvas, color);
+        }
+        if let Some(start) = path.first() {
+            start.put_pixel(&mut canvas, Rgb([255, 255, 255]));
+        }
+    }
+    return canvas;
+}
+
+/// Appends a strip below the visualization,
+/// labeling the color of each channel with the given names.
+pub fn add_legend(visualization: &RgbaImage, labels: &[&str]) -> RgbaImage {
+    let scale = 2;
+    let row_height = (text::GLYPH_HEIGHT + 2) * scale;
+    let (width, height) = visualization.dimensions();
+    let mut canvas = RgbaImage::from_pixel(
+        width,
+        height + row_height * labels.len() as u32,
+        Rgba([0, 0, 0, 255]),
+    );
+    imageops::replace(&mut canvas, visualization, 0, 0);
+    for (i, label) in labels.iter().enumerate() {
+        let top = height + i as u32 * row_height + scale;
+        let swatch_size = text::GLYPH_HEIGHT * scale;
+        for x in scale..scale + swatch_size {
+            for y in top..top + swatch_size {
+                if let Some(pixel) = canvas.get_pixel_mut_checked(x, y) {
+                    *pixel = generate_color(i).to_rgba();
+                }
+            }
+        }
+        text::draw_text(&mut canvas, label, 2 * scale + swatch_size, top, scale, Rgba([255; 4]));
+    }
+    return canvas;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+
+    /// Rules of a single pheromone channel without any updates.
+    fn plain_rules(ants: usize, parallelity: Option<usize>) -> AntColonyRules<SmallRng> {
+        return AntColonyRules::new(100, ants, false, parallelity, vec![vec![None]], None).unwrap();
+    }
+
+    #[test]
+    fn parallelity_above_ants_runs_every_ant_once() {
+        // Counts the ants in the first pixel.
+        let count_ant: Box<UpdateFunction<SmallRng>> =
+            Box::new(|_, _, pheromone, _| pheromone.get_pixel_mut(0, 0).0[0] += 1.0);
+        let functions = vec![vec![None], vec![Some(count_ant)]];
+        let rules = AntColonyRules::new(20, 3, false, Some(8), functions, None).unwrap();
+        assert_eq!(rules.parallelity, 3);
+        let img = RgbImage::from_pixel(10, 10, Rgb([128, 128, 128]));
+        let mut rng = SmallRng::seed_from_u64(635);
+        let mut pheromones = rules.initialize_pheromones(&mut rng, &img);
+        run_colony_step(&mut rng, &img, &rules, &mut pheromones);
+        assert_eq!(pheromones[0].get_pixel(0, 0).0[0], 3.0);
+    }
+
+    /// Total amount of steps onto pixels the ants visited before.
+    fn count_revisits(
+        img: &RgbImage, rules: &AntColonyRules<SmallRng>, pheromones: &[PheromoneImage],
+    ) -> usize {
+        let mut rng = SmallRng::seed_from_u64(810);
+        let mut revisits = 0;
+        for index in 0..rules.ants_per_global_update {
+            let mut ant = Ant::spawn_with_rules(&mut rng, img, rules, index);
+            ant.path = Some(vec![]);
+            ant.run(&mut rng, img, rules, pheromones);
+            revisits += ant.path.unwrap().len() - ant.visited.len();
+        }
+        return revisits;
+    }
+
+    #[test]
+    fn revisit_penalty_reduces_revisits() {
+        let img = RgbImage::from_pixel(30, 30, Rgb([128, 128, 128]));
+        let mut rules = plain_rules(50, Some(1));
+        let pheromones = rules.initialize_pheromones(&mut SmallRng::seed_from_u64(0), &img);
+        rules.heuristic.revisit_penalty = 0.0;
+        let unpenalized = count_revisits(&img, &rules, &pheromones);
+        rules.heuristic.revisit_penalty = 0.99;
+        let penalized = count_revisits(&img, &rules, &pheromones);
+        assert!(penalized < unpenalized, "{} revisits, {} without penalty", penalized, unpenalized);
+    }
+
+    /// Fraction of the steps of the ants spawned on the row that stay on it.
+    fn fraction_on_trail(rules: &mut AntColonyRules<SmallRng>, row: u32) -> f64 {
+        let img = RgbImage::from_pixel(30, 30, Rgb([128, 128, 128]));
+        let mut pheromones = rules.initialize_pheromones(&mut SmallRng::seed_from_u64(0), &img);
+        for x in 0..img.width() {
+            pheromones[0].put_pixel(x, row, Luma([1.0]));
+        }
+        rules.spawn_strategy =
+            SpawnStrategy::FromSet((0..img.width()).map(|x| Point::from((x, row))).collect());
+        let mut rng = SmallRng::seed_from_u64(812);
+        let (mut on_trail, mut steps) = (0, 0);
+        for index in 0..rules.ants_per_global_update {
+            let mut ant = Ant::spawn_with_rules(&mut rng, &img, rules, index);
+            ant.path = Some(vec![]);
+            ant.run(&mut rng, &img, rules, &pheromones);
+            let path = ant.path.unwrap();
+            on_trail += path.iter().filter(|p| p.y == row as i64).count();
+            steps += path.len();
+        }
+        return on_trail as f64 / steps as f64;
+    }
+
+    #[test]
+    fn high_alpha_follows_trail() {
+        let mut rules = plain_rules(50, Some(1));
+        rules.heuristic.alpha = 0.0;
+        let ignoring = fraction_on_trail(&mut rules, 15);
+        rules.heuristic.alpha = 5.0;
+        let following = fraction_on_trail(&mut rules, 15);
+        assert!(following > 0.8, "{} of steps on the trail", following);
+        assert!(
+            following > ignoring,
+            "{} of steps on the trail, {} at alpha 0",
+            following,
+            ignoring
+        );
+    }
+
+    #[test]
+    fn full_revisit_penalty_ends_walks() {
+        // Ants walking back and forth on a narrow image soon have no unvisited neighbours left.
+        let img = RgbImage::from_pixel(3, 2, Rgb([128, 128, 128]));
+        let mut rules =
+            AntColonyRules::new(200, 50, true, Some(1), vec![vec![None]], None).unwrap();
+        rules.heuristic.revisit_penalty = 1.0;
+        let pheromones = rules.initialize_pheromones(&mut SmallRng::seed_from_u64(0), &img);
+        let mut rng = SmallRng::seed_from_u64(810);
+        for index in 0..rules.ants_per_global_update {
+            let mut ant = Ant::spawn_with_rules(&mut rng, &img, &rules, index);
+            ant.run(&mut rng, &img, &rules, &pheromones);
+            let corner_b = Point { x: 2, y: 1 };
+            assert!(ant
+                .visited
+                .iter()
+                .all(|p| p.is_within_rectangle(&Point { x: 0, y: 0 }, &corner_b)));
+        }
+    }
+
+    #[test]
+    fn candidate_lists_without_weights_end_walks() {
+        let img = RgbImage::from_pixel(3, 2, Rgb([128, 128, 128]));
+        for candidate_list in [CandidateList::Strongest(1), CandidateList::Random(1)] {
+            let mut rules =
+                AntColonyRules::new(200, 20, true, Some(1), vec![vec![None]], None).unwrap();
+            rules.heuristic.revisit_penalty = 1.0;
+            rules.candidate_list = candidate_list;
+            let pheromones = rules.initialize_pheromones(&mut SmallRng::seed_from_u64(0), &img);
+            let mut rng = SmallRng::seed_from_u64(673);
+            for index in 0..rules.ants_per_global_update {
+                let mut ant = Ant::spawn_with_rules(&mut rng, &img, &rules, index);
+                ant.run(&mut rng, &img, &rules, &pheromones);
+                assert!(ant.steps > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn target_bias_must_exceed_longest_step() {
+        let mut heuristic = AntHeuristicParams::default();
+        assert_eq!(heuristic.check(), Ok(()));
+        for target_bias in [1.0, std::f32::consts::SQRT_2, f32::NAN] {
+            heuristic.target_bias = target_bias;
+            assert_eq!(heuristic.check(), Err(RulesError::TargetBias));
+        }
+    }
+
+    #[test]
+    fn ants_never_spawn_on_their_target() {
+        let mut rng = SmallRng::seed_from_u64(665);
+        for (width, height) in [(2, 1), (1, 3), (3, 3), (40, 30)] {
+            for max_target_distance in [None, Some(1), Some(5)] {
+                for _ in 0..200 {
+                    let ant = Ant::spawn(&mut rng, width, height, max_target_distance);
+                    assert_ne!(ant.position, ant.target);
+                }
+            }
+        }
+        // Without any other pixel in reach, the ant stays where it is.
+        let ant = Ant::spawn(&mut rng, 1, 1, None);
+        assert_eq!(ant.position, ant.target);
+        let ant = Ant::spawn(&mut rng, 10, 10, Some(0));
+        assert_eq!(ant.position, ant.target);
+    }
+
+    #[test]
+    fn from_set_only_spawns_at_given_points() {
+        let img = RgbImage::new(20, 20);
+        let mut rules = plain_rules(40, Some(1));
+        let points = vec![
+            Point { x: 3, y: 4 },
+            Point { x: 17, y: 0 },
+            Point { x: 9, y: 19 },
+        ];
+        rules.spawn_strategy = SpawnStrategy::FromSet(points.clone());
+        let mut rng = SmallRng::seed_from_u64(811);
+        for index in 0..100 {
+            let ant = Ant::spawn_with_rules(&mut rng, &img, &rules, index);
+            assert_eq!(ant.position, points[index % points.len()]);
+        }
+    }
+
+    #[test]
+    fn from_empty_set_spawns_uniformly() {
+        let img = RgbImage::new(20, 20);
+        let mut rules = plain_rules(40, Some(1));
+        rules.spawn_strategy = SpawnStrategy::FromSet(vec![]);
+        let mut rng = SmallRng::seed_from_u64(811);
+        let corner_b = Point { x: 19, y: 19 };
+        let positions: HashSet<_> = (0..100)
+            .map(|index| Ant::spawn_with_rules(&mut rng, &img, &rules, index).position)
+            .collect();
+        assert!(positions.iter().all(|p| p.is_within_rectangle(&Point { x: 0, y: 0 }, &corner_b)));
+        assert!(positions.len() > 50);
+    }
+}
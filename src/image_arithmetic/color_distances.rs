@@ -1,37 +1,57 @@
-use image::Rgb;
+//! Distances between colors, generic over the channel type
+//! so that images with more than 8 bits per channel can be compared as well.
 
-fn multiply(x: u8, y: u8) -> f64 {
-    ((x as u16) * (y as u16)) as f64
+use image::{Primitive, Rgb};
+
+fn value<T: Primitive>(x: T) -> f64 {
+    x.to_f64().unwrap()
+}
+
+fn multiply<T: Primitive>(x: T, y: T) -> f64 {
+    value(x) * value(y)
 }
 
-fn square(x: u8) -> f64 {
-    multiply(x, x)
+fn square(x: f64) -> f64 {
+    x * x
 }
 
-pub fn euclidean_squared(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
-    square(b.0[0] - a.0[0]) + square(b.0[1] - a.0[1]) + square(b.0[2] - a.0[2])
+fn diff<T: Primitive>(a: T, b: T) -> f64 {
+    value(b) - value(a)
 }
 
-pub fn euclidean(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+pub fn euclidean_squared<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>) -> f64 {
+    square(diff(a.0[0], b.0[0])) + square(diff(a.0[1], b.0[1])) + square(diff(a.0[2], b.0[2]))
+}
+
+pub fn euclidean<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>) -> f64 {
     euclidean_squared(a, b).sqrt()
 }
 
-fn absdiff(a: u8, b: u8) -> f64 {
-    (((b as i16) - (a as i16)) as f64).abs()
+/// Euclidean distance with each channel's difference scaled by its weight,
+/// to emphasize the channels carrying the signal.
+pub fn weighted_euclidean<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>, weights: &[f64; 3]) -> f64 {
+    (square(weights[0] * diff(a.0[0], b.0[0]))
+        + square(weights[1] * diff(a.0[1], b.0[1]))
+        + square(weights[2] * diff(a.0[2], b.0[2])))
+    .sqrt()
+}
+
+fn absdiff<T: Primitive>(a: T, b: T) -> f64 {
+    diff(a, b).abs()
 }
 
-pub fn manhattan(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+pub fn manhattan<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>) -> f64 {
     absdiff(a.0[0], b.0[0]) + absdiff(a.0[1], b.0[1]) + absdiff(a.0[2], b.0[2])
 }
 
-fn magnitude(x: &Rgb<u8>) -> f64 {
-    (square(x.0[0]) + square(x.0[1]) + square(x.0[2])).sqrt()
+fn magnitude<T: Primitive>(x: &Rgb<T>) -> f64 {
+    (square(value(x.0[0])) + square(value(x.0[1])) + square(value(x.0[2]))).sqrt()
 }
 
-pub fn cosine_unnormed(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+pub fn cosine_unnormed<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>) -> f64 {
     multiply(a.0[0], b.0[0]) + multiply(a.0[1], b.0[1]) + multiply(a.0[2], b.0[2])
 }
 
-pub fn cosine(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+pub fn cosine<T: Primitive>(a: &Rgb<T>, b: &Rgb<T>) -> f64 {
     cosine_unnormed(a, b) / (magnitude(a) * magnitude(b))
 }
@@ -7,7 +7,10 @@ pub mod types;
 pub use self::types::*;
 pub mod utilities;
 pub use self::utilities::*;
+pub mod quantize;
 pub mod segments;
+pub mod synthetic;
+pub mod text;
 
-pub const LAPLACE_KERNEL: &[f32] = &[1.0, 1.0, 1.0, 1.0, -8.0, 1.0, 1.0, 1.0, 1.0];
-pub const STRAIGHT_LAPLACE_KERNEL: &[f32] = &[0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0];
+pub const LAPLACE_KERNEL: &[f32; 9] = &[1.0, 1.0, 1.0, 1.0, -8.0, 1.0, 1.0, 1.0, 1.0];
+pub const STRAIGHT_LAPLACE_KERNEL: &[f32; 9] = &[0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0];
@@ -0,0 +1,130 @@
+//! Color quantization, reducing images to a small palette.
+
+use std::collections::HashMap;
+
+use image::{Rgb, RgbImage};
+
+/// Weighted mean of colors given with their number of occurrences.
+fn mean_color(colors: &[([u8; 3], u32)]) -> Rgb<u8> {
+    let total: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+    let mut sums = [0u64; 3];
+    for &(color, count) in colors {
+        for c in 0..3 {
+            sums[c] += color[c] as u64 * count as u64;
+        }
+    }
+    return Rgb(sums.map(|sum| ((sum + total / 2) / total) as u8));
+}
+
+/// Channel with the largest range of values and that range.
+fn widest_channel(colors: &[([u8; 3], u32)]) -> (usize, u8) {
+    return (0..3)
+        .map(|c| {
+            let values = colors.iter().map(|&(color, _)| color[c]);
+            (c, values.clone().max().unwrap() - values.min().unwrap())
+        })
+        .max_by_key(|&(c, range)| (range, std::cmp::Reverse(c)))
+        .unwrap();
+}
+
+/// Palette of at most `colors` colors for the image, found by median cut.
+///
+/// The distinct colors of the image are recursively split into boxes along the channel with
+/// the largest range, at the median weighted by the number of pixels of each color.
+/// Every box is represented by the mean color of its pixels.
+/// Returns a mapping from every color of the image to its palette color.
+pub fn median_cut_palette(img: &RgbImage, colors: usize) -> HashMap<[u8; 3], Rgb<u8>> {
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in img.pixels() {
+        *histogram.entry(pixel.0).or_insert(0) += 1;
+    }
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![histogram.into_iter().collect()];
+    boxes.retain(|entries| !entries.is_empty());
+    while boxes.len() < colors {
+        // Split the box that spans the largest range, if any box can be split at all.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, entries)| (i, widest_channel(entries)))
+            .filter(|&(_, (_, range))| range > 0)
+            .max_by_key(|&(i, (_, range))| (range, std::cmp::Reverse(i)));
+        let (index, channel) = match widest {
+            Some((index, (channel, _))) => (index, channel),
+            None => break,
+        };
+        let mut entries = boxes.swap_remove(index);
+        entries.sort_unstable_by_key(|&(color, _)| (color[channel], color));
+        let total: u64 = entries.iter().map(|&(_, count)| count as u64).sum();
+        let mut seen = 0;
+        // Both halves keep at least one color, as the range is not zero.
+        let mut split = entries.len() - 1;
+        for (i, &(_, count)) in entries.iter().enumerate().take(entries.len() - 1) {
+            seen += count as u64;
+            if 2 * seen >= total {
+                split = i + 1;
+                break;
+            }
+        }
+        let upper = entries.split_off(split);
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+    let mut palette = HashMap::new();
+    for entries in boxes {
+        let mean = mean_color(&entries);
+        palette.extend(entries.into_iter().map(|(color, _)| (color, mean)));
+    }
+    return palette;
+}
+
+/// Reduce the image to at most `colors` colors using median cut, see [median_cut_palette].
+pub fn quantize_median_cut(img: &RgbImage, colors: usize) -> RgbImage {
+    let palette = median_cut_palette(img, colors);
+    let mut result = img.clone();
+    for pixel in result.pixels_mut() {
+        *pixel = palette[&pixel.0];
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    fn distinct_colors(img: &RgbImage) -> HashSet<[u8; 3]> {
+        return img.pixels().map(|pixel| pixel.0).collect();
+    }
+
+    #[test]
+    fn palette_holds_at_most_the_given_colors() {
+        let mut rng = SmallRng::seed_from_u64(683);
+        let img = RgbImage::from_fn(40, 30, |_, _| Rgb(rng.gen()));
+        for colors in [1, 2, 5, 16, 100] {
+            let quantized = quantize_median_cut(&img, colors);
+            assert!(distinct_colors(&quantized).len() <= colors);
+        }
+        assert_eq!(distinct_colors(&quantize_median_cut(&img, 16)).len(), 16);
+    }
+
+    #[test]
+    fn single_color_image_is_unchanged() {
+        let img = RgbImage::from_pixel(9, 7, Rgb([30, 140, 250]));
+        for colors in [1, 2, 8] {
+            assert_eq!(quantize_median_cut(&img, colors), img);
+        }
+    }
+
+    #[test]
+    fn enough_colors_reproduce_the_image() {
+        let mut rng = SmallRng::seed_from_u64(683);
+        let palette: Vec<Rgb<u8>> = (0..6).map(|_| Rgb(rng.gen())).collect();
+        let img = RgbImage::from_fn(20, 20, |_, _| palette[rng.gen_range(0..palette.len())]);
+        let distinct = distinct_colors(&img).len();
+        for colors in [distinct, distinct + 1, 256] {
+            assert_eq!(quantize_median_cut(&img, colors), img);
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use super::utilities;
-use super::{ColorSpaceDistance, Point};
+use super::{ColorSpaceDistance, Connectivity, Point};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::thread;
 
 use image::{Rgb, RgbImage};
 
@@ -29,76 +30,1101 @@ pub fn overall_deviation(
     return segments.iter().map(|s| segment_deviation(img, s, dist)).sum();
 }
 
+/// Levels each color channel is quantized to for `segment_entropy`.
+pub const ENTROPY_LEVELS: u32 = 8;
+
+/// Shannon entropy in nats of the colors of the segment,
+/// quantized to `ENTROPY_LEVELS` per channel, so that noise does not count as variety.
+/// Homogeneous segments have low entropy, no matter how far apart their few colors are.
+pub fn segment_entropy(img: &RgbImage, segment: &HashSet<Point>) -> f64 {
+    let mut histogram = HashMap::new();
+    for point in segment {
+        let bin = point.get_pixel(img).0.map(|c| c as u32 * ENTROPY_LEVELS / 256);
+        *histogram.entry(bin).or_insert(0usize) += 1;
+    }
+    let total = segment.len() as f64;
+    return histogram
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total;
+            return -p * p.ln();
+        })
+        .sum();
+}
+
+/// Entropy of all segments, weighted by their sizes like the per-pixel sums of the other measures.
+pub fn overall_entropy(img: &RgbImage, segments: &Vec<HashSet<Point>>) -> f64 {
+    return segments.iter().map(|s| s.len() as f64 * segment_entropy(img, s)).sum();
+}
+
+/// Only neighbours of the given connectivity are considered.
+/// Neighbours are wrapped around the edges of the image if `wrap` is set,
+/// otherwise neighbours outside of the image do not contribute.
 pub fn local_edge_value(
     img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance, point: &Point,
+    connectivity: Connectivity, wrap: bool,
 ) -> f64 {
     let segment = find_segment(segments, point);
-    let corner_a = Point { x: 0, y: 0 };
-    let corner_b = Point { x: (img.width() - 1) as i64, y: (img.height() - 1) as i64 };
     return point
-        .iterate_neighbourhood()
-        .map(|neighbour| {
-            if segment.map_or(false, |x| x.contains(&neighbour))
-                || !neighbour.is_within_rectangle(&corner_a, &corner_b)
-            {
-                return 0.0;
-            }
-            return dist(point.get_pixel(img), neighbour.get_pixel(img));
+        .iterate_connected(connectivity)
+        .map(|neighbour| match neighbour.within(img.width(), img.height(), wrap) {
+            Some(neighbour) if !segment.map_or(false, |x| x.contains(&neighbour)) => {
+                return dist(point.get_pixel(img), neighbour.get_pixel(img));
+            }
+            _ => 0.0,
         })
         .sum();
 }
 
 pub fn edge_value(
     img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance,
+    connectivity: Connectivity, wrap: bool,
+) -> f64 {
+    return img
+        .enumerate_pixels()
+        .map(|(x, y, _)| local_edge_value(img, segments, dist, &(x, y).into(), connectivity, wrap))
+        .sum();
+}
+
+/// Horizontal and vertical Sobel responses around the point, pointing towards brighter colors.
+/// Each color channel is filtered separately and the strongest response is returned,
+/// so that edges between colors of similar brightness are found as well.
+/// Pixels beyond the edges repeat the outermost ones, unless `wrap` is set.
+pub fn sobel_gradient(img: &RgbImage, point: &Point, wrap: bool) -> (f64, f64) {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let pixel = |dx: i64, dy: i64| {
+        let (x, y) = if wrap {
+            ((point.x + dx).rem_euclid(width), (point.y + dy).rem_euclid(height))
+        } else {
+            ((point.x + dx).clamp(0, width - 1), (point.y + dy).clamp(0, height - 1))
+        };
+        return img.get_pixel(x as u32, y as u32);
+    };
+    let neighbours: Vec<Vec<&Rgb<u8>>> =
+        (-1..=1).map(|dy| (-1..=1).map(|dx| pixel(dx, dy)).collect()).collect();
+    return (0..3)
+        .map(|c| {
+            let value = |dx: usize, dy: usize| neighbours[dy][dx].0[c] as f64;
+            let gx = value(2, 0) + 2.0 * value(2, 1) + value(2, 2)
+                - value(0, 0)
+                - 2.0 * value(0, 1)
+                - value(0, 2);
+            let gy = value(0, 2) + 2.0 * value(1, 2) + value(2, 2)
+                - value(0, 0)
+                - 2.0 * value(1, 0)
+                - value(2, 0);
+            (gx, gy)
+        })
+        .max_by(|a, b| a.0.hypot(a.1).total_cmp(&b.0.hypot(b.1)))
+        .unwrap();
+}
+
+/// Like `local_edge_value`, but weights each neighbour from another segment by how well
+/// the direction towards it agrees with the image gradient at the point, see `sobel_gradient`.
+/// Boundaries running along edges of the image separate neighbours across the gradient,
+/// which count fully, while boundaries cutting through edges count little.
+/// Without any gradient, all neighbours count fully like in `local_edge_value`.
+pub fn local_gradient_edge_value(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance, point: &Point,
+    connectivity: Connectivity, wrap: bool,
+) -> f64 {
+    let segment = find_segment(segments, point);
+    let (gx, gy) = sobel_gradient(img, point, wrap);
+    let magnitude = gx.hypot(gy);
+    return point
+        .iterate_connected(connectivity)
+        .map(|neighbour| match neighbour.within(img.width(), img.height(), wrap) {
+            Some(wrapped) if !segment.map_or(false, |x| x.contains(&wrapped)) => {
+                let (dx, dy) = ((neighbour.x - point.x) as f64, (neighbour.y - point.y) as f64);
+                let agreement = if magnitude > 0.0 {
+                    (dx * gx + dy * gy).abs() / (magnitude * dx.hypot(dy))
+                } else {
+                    1.0
+                };
+                return agreement * dist(point.get_pixel(img), wrapped.get_pixel(img));
+            }
+            _ => 0.0,
+        })
+        .sum();
+}
+
+pub fn gradient_edge_value(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance,
+    connectivity: Connectivity, wrap: bool,
 ) -> f64 {
     return img
         .enumerate_pixels()
-        .map(|(x, y, _)| local_edge_value(img, segments, dist, &(x, y).into()))
+        .map(|(x, y, _)| {
+            let point = (x, y).into();
+            local_gradient_edge_value(img, segments, dist, &point, connectivity, wrap)
+        })
         .sum();
 }
 
+/// How neighbours belonging to other segments are weighted by the edge value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EdgeWeighting {
+    /// All neighbours count fully, regardless of their direction.
+    Isotropic,
+    /// Neighbours are weighted by the agreement of their direction with the image gradient,
+    /// see `local_gradient_edge_value`.
+    Gradient,
+}
+
+impl EdgeWeighting {
+    pub fn local_value(
+        self, img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance,
+        point: &Point, connectivity: Connectivity, wrap: bool,
+    ) -> f64 {
+        return match self {
+            Self::Isotropic => local_edge_value(img, segments, dist, point, connectivity, wrap),
+            Self::Gradient => {
+                local_gradient_edge_value(img, segments, dist, point, connectivity, wrap)
+            }
+        };
+    }
+
+    pub fn value(
+        self, img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance,
+        connectivity: Connectivity, wrap: bool,
+    ) -> f64 {
+        return match self {
+            Self::Isotropic => edge_value(img, segments, dist, connectivity, wrap),
+            Self::Gradient => gradient_edge_value(img, segments, dist, connectivity, wrap),
+        };
+    }
+}
+
+/// Edge value of a segmentation only given by its contour, without filling its regions.
+/// Pairs of neighbours count if at least one of them lies on the contour,
+/// which matches `edge_value` of the regions between the contours,
+/// except for diagonal neighbours on opposite sides of a contour, which do not count here.
+/// The connectivity measure and overall deviation depend on the regions,
+/// so they have no counterpart for contours.
+pub fn contour_edge_value(
+    img: &RgbImage, contour: &HashSet<Point>, dist: &ColorSpaceDistance,
+    connectivity: Connectivity, wrap: bool,
+) -> f64 {
+    let regions = img
+        .enumerate_pixels()
+        .map(|(x, y, _)| Point::from((x, y)))
+        .filter(|point| !contour.contains(point))
+        .collect();
+    return edge_value(img, &vec![regions], dist, connectivity, wrap);
+}
+
 pub fn local_connectivity_measure(
-    img: &RgbImage, segments: &Vec<HashSet<Point>>, point: &Point,
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, point: &Point, connectivity: Connectivity,
+    wrap: bool,
 ) -> f64 {
     let segment = find_segment(segments, point);
-    let corner_a = Point { x: 0, y: 0 };
-    let corner_b = Point { x: (img.width() - 1) as i64, y: (img.height() - 1) as i64 };
     return point
-        .iterate_neighbourhood()
+        .iterate_connected(connectivity)
         .enumerate()
-        .map(|(i, neighbour)| {
-            if segment.map_or(false, |x| x.contains(&neighbour))
-                || !neighbour.is_within_rectangle(&corner_a, &corner_b)
-            {
-                return 0.0;
+        .map(|(i, neighbour)| match neighbour.within(img.width(), img.height(), wrap) {
+            Some(neighbour) if !segment.map_or(false, |x| x.contains(&neighbour)) => {
+                return 1.0 / (i + 1) as f64;
             }
-            return 1.0 / (i + 1) as f64;
+            _ => 0.0,
         })
         .sum();
 }
 
 pub fn connectivity_measure(
     img: &RgbImage, segments: &Vec<HashSet<Point>>, _dist: &ColorSpaceDistance,
+    connectivity: Connectivity, wrap: bool,
+) -> f64 {
+    return img
+        .enumerate_pixels()
+        .map(|(x, y, _)| {
+            local_connectivity_measure(img, segments, &(x, y).into(), connectivity, wrap)
+        })
+        .sum();
+}
+
+/// Like `local_connectivity_measure`, but weights each neighbour from another segment
+/// by the inverse of its geometric distance instead of its index in the neighbourhood.
+pub fn local_distance_connectivity_measure(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, point: &Point, connectivity: Connectivity,
+    wrap: bool,
+) -> f64 {
+    let segment = find_segment(segments, point);
+    return point
+        .iterate_connected(connectivity)
+        .map(|neighbour| match neighbour.within(img.width(), img.height(), wrap) {
+            Some(wrapped) if !segment.map_or(false, |x| x.contains(&wrapped)) => {
+                return 1.0 / point.euclidean_distance(&neighbour);
+            }
+            _ => 0.0,
+        })
+        .sum();
+}
+
+pub fn distance_connectivity_measure(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, _dist: &ColorSpaceDistance,
+    connectivity: Connectivity, wrap: bool,
 ) -> f64 {
     return img
         .enumerate_pixels()
-        .map(|(x, y, _)| local_connectivity_measure(img, segments, &(x, y).into()))
+        .map(|(x, y, _)| {
+            let point = (x, y).into();
+            local_distance_connectivity_measure(img, segments, &point, connectivity, wrap)
+        })
         .sum();
 }
 
-pub fn extract_segments(contour: &RgbImage) -> (RgbImage, Vec<HashSet<Point>>) {
+/// How neighbours belonging to other segments are penalized by the connectivity measure.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConnectivityWeighting {
+    /// The i-th neighbour in `Point::neighbourhood_directions` is weighted by 1/(i+1).
+    /// Depends on the arbitrary ordering of the directions.
+    Index,
+    /// Neighbours are weighted by the inverse of their distance,
+    /// so orthogonal neighbours count fully and diagonal ones by 1/sqrt(2).
+    Distance,
+}
+
+impl ConnectivityWeighting {
+    pub fn local_measure(
+        self, img: &RgbImage, segments: &Vec<HashSet<Point>>, point: &Point,
+        connectivity: Connectivity, wrap: bool,
+    ) -> f64 {
+        return match self {
+            Self::Index => local_connectivity_measure(img, segments, point, connectivity, wrap),
+            Self::Distance => {
+                local_distance_connectivity_measure(img, segments, point, connectivity, wrap)
+            }
+        };
+    }
+
+    pub fn measure(
+        self, img: &RgbImage, segments: &Vec<HashSet<Point>>, dist: &ColorSpaceDistance,
+        connectivity: Connectivity, wrap: bool,
+    ) -> f64 {
+        return match self {
+            Self::Index => connectivity_measure(img, segments, dist, connectivity, wrap),
+            Self::Distance => {
+                distance_connectivity_measure(img, segments, dist, connectivity, wrap)
+            }
+        };
+    }
+}
+
+/// Disjoint sets of indices, merged with union by size and path halving.
+struct DisjointSets {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        return Self { parents: (0..len).collect(), sizes: vec![1; len] };
+    }
+
+    /// Joins sets of consecutive ranges of elements, none of which are united across ranges.
+    fn concat(parts: Vec<Self>) -> Self {
+        let mut result = Self { parents: vec![], sizes: vec![] };
+        for part in parts {
+            let offset = result.parents.len();
+            result.parents.extend(part.parents.iter().map(|parent| parent + offset));
+            result.sizes.extend(part.sizes);
+        }
+        return result;
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        return i;
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (larger, smaller) = if self.sizes[a] >= self.sizes[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parents[smaller] = larger;
+        self.sizes[larger] += self.sizes[smaller];
+    }
+}
+
+/// Rows of an image below which components are labeled on a single thread,
+/// as spawning threads would take longer than labeling the rows.
+const MIN_ROWS_PER_THREAD: u32 = 32;
+
+/// Connected components of all pixels with the foreground color,
+/// in raster order of their top-left-most pixel.
+/// If `wrap` is set, components continue across the edges of the image.
+/// Components are labeled with a union-find pass over the image,
+/// which takes close to linear time regardless of the number of components.
+/// Large images are split into bands of rows that are labeled in parallel,
+/// their components are then merged across the borders between the bands.
+/// The components do not depend on the number of bands.
+pub fn connected_components(
+    mask: &RgbImage, foreground: Rgb<u8>, connectivity: Connectivity, wrap: bool,
+) -> Vec<HashSet<Point>> {
+    let threads = thread::available_parallelism().map_or(1, |x| x.get()) as u32;
+    let bands = threads.min(mask.height() / MIN_ROWS_PER_THREAD).max(1);
+    return banded_connected_components(mask, foreground, connectivity, wrap, bands);
+}
+
+/// `connected_components`, labeled in the given number of bands of rows.
+fn banded_connected_components(
+    mask: &RgbImage, foreground: Rgb<u8>, connectivity: Connectivity, wrap: bool, bands: u32,
+) -> Vec<HashSet<Point>> {
+    let width = mask.width();
+    let mut sets = label_components(mask, foreground, connectivity, wrap, bands);
+    let index = |point: Point| (point.y * width as i64 + point.x) as usize;
+    // Numbering roots when they are first encountered keeps the raster order.
+    let mut components: Vec<HashSet<Point>> = vec![];
+    let mut numbers = HashMap::new();
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if *pixel != foreground {
+            continue;
+        }
+        let point = Point::from((x, y));
+        let root = sets.find(index(point));
+        let number = *numbers.entry(root).or_insert_with(|| {
+            components.push(HashSet::new());
+            return components.len() - 1;
+        });
+        components[number].insert(point);
+    }
+    return components;
+}
+
+/// Unites the pixels of the components of `connected_components`,
+/// labeling each of the given number of bands of rows on its own thread.
+fn label_components(
+    mask: &RgbImage, foreground: Rgb<u8>, connectivity: Connectivity, wrap: bool, bands: u32,
+) -> DisjointSets {
+    let (width, height) = mask.dimensions();
+    let rows_per_band = height.div_ceil(bands);
+    let band_of = |y: i64| y as u32 / rows_per_band;
+    // Only unites pixels within the band, returning the sets of its pixels.
+    let label_band = |band: u32| {
+        let first_row = band * rows_per_band;
+        let rows = rows_per_band.min(height - first_row);
+        let index = |point: Point| ((point.y - first_row as i64) * width as i64 + point.x) as usize;
+        let mut sets = DisjointSets::new((width * rows) as usize);
+        for y in first_row..first_row + rows {
+            for x in 0..width {
+                if *mask.get_pixel(x, y) != foreground {
+                    continue;
+                }
+                let point = Point::from((x, y));
+                for npoint in point.iterate_connected(connectivity) {
+                    match npoint.within(width, height, wrap) {
+                        Some(npoint)
+                            if band_of(npoint.y) == band
+                                && *npoint.get_pixel(mask) == foreground =>
+                        {
+                            sets.union(index(point), index(npoint))
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+        return sets;
+    };
+    // Bands are rounded up to whole rows, so there may be fewer than requested.
+    let bands: Vec<u32> = (0..height.div_ceil(rows_per_band)).collect();
+    if bands.len() == 1 {
+        return label_band(0);
+    }
+    let parts: Vec<DisjointSets> = thread::scope(|scope| {
+        let label_band = &label_band;
+        let threads: Vec<_> =
+            bands.iter().map(|&band| scope.spawn(move || label_band(band))).collect();
+        return threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+    });
+    let mut sets = DisjointSets::concat(parts);
+    // Components can only continue into other bands from their first or last row.
+    let index = |point: Point| (point.y * width as i64 + point.x) as usize;
+    for band in bands {
+        let first_row = band * rows_per_band;
+        let last_row = (first_row + rows_per_band).min(height) - 1;
+        for y in [first_row, last_row] {
+            for x in 0..width {
+                if *mask.get_pixel(x, y) != foreground {
+                    continue;
+                }
+                let point = Point::from((x, y));
+                for npoint in point.iterate_connected(connectivity) {
+                    match npoint.within(width, height, wrap) {
+                        Some(npoint)
+                            if band_of(npoint.y) != band
+                                && *npoint.get_pixel(mask) == foreground =>
+                        {
+                            sets.union(index(point), index(npoint))
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+    }
+    return sets;
+}
+
+/// Fills every white region of the contour image with a unique color.
+/// Regions are grown using the given connectivity,
+/// which should complement the connectivity of the contour lines.
+/// Segments are found in raster order, see `SegmentOrder::Raster`.
+/// If `wrap` is set, segments continue across the edges of the image.
+pub fn extract_segments(
+    contour: &RgbImage, connectivity: Connectivity, wrap: bool,
+) -> (RgbImage, Vec<HashSet<Point>>) {
+    let segments = connected_components(contour, Rgb([255, 255, 255]), connectivity, wrap);
     let mut p = contour.clone();
-    let mut segments = vec![];
-    loop {
-        // Find blank pixel.
-        let blank = p.enumerate_pixels().find(|(_, _, &p)| p == Rgb([255, 255, 255]));
-        if blank.is_none() {
+    recolor_segments(&mut p, &segments);
+    return (p, segments);
+}
+
+/// Index of the segment of each pixel in raster order, none for pixels not in any segment.
+fn label_pixels(segments: &Vec<HashSet<Point>>, width: u32, height: u32) -> Vec<Option<usize>> {
+    let mut labels = vec![None; (width * height) as usize];
+    for (i, segment) in segments.iter().enumerate() {
+        for point in segment {
+            labels[(point.y * width as i64 + point.x) as usize] = Some(i);
+        }
+    }
+    return labels;
+}
+
+/// Segments of an image together with an index of which segment each pixel belongs to.
+#[derive(Debug, Clone)]
+pub struct Segmentation {
+    pub segments: Vec<HashSet<Point>>,
+    width: u32,
+    height: u32,
+    labels: Vec<Option<usize>>,
+}
+
+impl Segmentation {
+    /// Indexes the segments of an image of the given dimensions.
+    /// Segments are expected to be disjoint, otherwise later segments take precedence.
+    pub fn new(segments: Vec<HashSet<Point>>, width: u32, height: u32) -> Self {
+        let labels = label_pixels(&segments, width, height);
+        return Self { segments, width, height, labels };
+    }
+
+    /// Index of the segment the point belongs to,
+    /// none if it lies on a contour or outside of the image.
+    pub fn segment_of(&self, point: &Point) -> Option<usize> {
+        let point = point.within(self.width, self.height, false)?;
+        return self.labels[(point.y * self.width as i64 + point.x) as usize];
+    }
+
+    pub fn segment_count(&self) -> usize {
+        return self.segments.len();
+    }
+}
+
+/// Segments of a contour image, remembering which segment each pixel belongs to,
+/// so that they can be updated incrementally when the contour changes.
+#[derive(Debug, Clone)]
+pub struct LabeledSegments {
+    pub contour: RgbImage,
+    pub segments: Vec<HashSet<Point>>,
+    connectivity: Connectivity,
+    wrap: bool,
+    labels: Vec<Option<usize>>,
+}
+
+impl LabeledSegments {
+    /// Changing more than this fraction of the contour triggers a full recomputation.
+    pub const MAX_INCREMENTAL_CHANGE: f64 = 0.25;
+
+    /// If `wrap` is set, segments continue across the edges of the image.
+    pub fn new(contour: RgbImage, connectivity: Connectivity, wrap: bool) -> Self {
+        let segments = connected_components(&contour, Rgb([255, 255, 255]), connectivity, wrap);
+        let (width, height) = contour.dimensions();
+        let Segmentation { segments, labels, .. } = Segmentation::new(segments, width, height);
+        return Self { contour, segments, connectivity, wrap, labels };
+    }
+
+    fn index(&self, point: &Point) -> usize {
+        return (point.y * self.contour.width() as i64 + point.x) as usize;
+    }
+
+    /// Index of the segment the point belongs to, if any.
+    pub fn label(&self, point: &Point) -> Option<usize> {
+        return self.labels[self.index(point)];
+    }
+
+    /// Updates the segments to match the new contour.
+    /// Only segments touching changed pixels are recomputed,
+    /// unless the change is large, then all segments are recomputed.
+    /// Indices of unaffected segments may change, their order is not preserved.
+    pub fn update(&mut self, contour: RgbImage) {
+        let (width, height) = contour.dimensions();
+        if contour.dimensions() != self.contour.dimensions() {
+            *self = Self::new(contour, self.connectivity, self.wrap);
+            return;
+        }
+        let changed: Vec<Point> = contour
+            .enumerate_pixels()
+            .filter(|(x, y, pixel)| self.contour.get_pixel(*x, *y) != *pixel)
+            .map(|(x, y, _)| Point::from((x, y)))
+            .collect();
+        if changed.len() as f64 > Self::MAX_INCREMENTAL_CHANGE * (width * height) as f64 {
+            *self = Self::new(contour, self.connectivity, self.wrap);
+            return;
+        }
+        self.contour = contour;
+        // Segments next to changed pixels may be split or merged.
+        let mut affected = HashSet::new();
+        for point in &changed {
+            for neighbour in point.iterate_neighbourhood().chain([*point]) {
+                if let Some(neighbour) = neighbour.within(width, height, self.wrap) {
+                    affected.extend(self.label(&neighbour));
+                }
+            }
+        }
+        let mut seeds = changed;
+        for &i in &affected {
+            let segment = std::mem::take(&mut self.segments[i]);
+            for point in &segment {
+                let index = self.index(point);
+                self.labels[index] = None;
+            }
+            seeds.extend(segment);
+        }
+        for point in &seeds {
+            let index = self.index(point);
+            self.labels[index] = None;
+        }
+        // Fill the affected area again, reusing the slots of the affected segments.
+        let mut free: Vec<usize> = affected.into_iter().collect();
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        let white = Rgb([255, 255, 255]);
+        for seed in seeds {
+            if *seed.get_pixel(&self.contour) != white || self.label(&seed).is_some() {
+                continue;
+            }
+            let slot = free.pop().unwrap_or(self.segments.len());
+            if slot == self.segments.len() {
+                self.segments.push(HashSet::new());
+            }
+            let mut queued = vec![seed];
+            let index = self.index(&seed);
+            self.labels[index] = Some(slot);
+            while let Some(point) = queued.pop() {
+                for neighbour in point.iterate_connected(self.connectivity) {
+                    let neighbour = match neighbour.within(width, height, self.wrap) {
+                        Some(neighbour) => neighbour,
+                        None => continue,
+                    };
+                    if *neighbour.get_pixel(&self.contour) != white
+                        || self.label(&neighbour).is_some()
+                    {
+                        continue;
+                    }
+                    let index = self.index(&neighbour);
+                    self.labels[index] = Some(slot);
+                    queued.push(neighbour);
+                }
+                self.segments[slot].insert(point);
+            }
+        }
+        // Remove slots that are still empty, moving the last segments into their place.
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        for slot in free {
+            self.segments.swap_remove(slot);
+            if slot < self.segments.len() {
+                for point in &self.segments[slot] {
+                    let index = (point.y * width as i64 + point.x) as usize;
+                    self.labels[index] = Some(slot);
+                }
+            }
+        }
+    }
+}
+
+/// Order in which segments are indexed, and thus colored.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentOrder {
+    /// By their top-left-most pixel in raster order.
+    /// A segment appearing near the top of the image shifts the indices of all following ones.
+    Raster,
+    /// By decreasing size, ties are broken by raster order.
+    /// Large segments keep their indices if the contour differs only slightly.
+    Size,
+}
+
+fn first_in_raster_order(segment: &HashSet<Point>) -> Option<(i64, i64)> {
+    return segment.iter().map(|p| (p.y, p.x)).min();
+}
+
+pub fn sort_segments(segments: &mut Vec<HashSet<Point>>, order: SegmentOrder) {
+    match order {
+        SegmentOrder::Raster => segments.sort_by_cached_key(first_in_raster_order),
+        SegmentOrder::Size => segments.sort_by_cached_key(|segment| {
+            (std::cmp::Reverse(segment.len()), first_in_raster_order(segment))
+        }),
+    }
+}
+
+/// Distance across which pixels may be reassigned to another segment by `refine_boundaries`.
+/// Segments are separated by contour lines, so neighbouring segments are not directly adjacent.
+const REFINEMENT_RADIUS: i64 = 2;
+
+/// Reassigns pixels near the boundary of their segment to a nearby segment
+/// whose mean color is closer to theirs, snapping boundaries to color edges.
+/// Each pass compares against the means from before the pass,
+/// segments are never emptied completely.
+pub fn refine_boundaries(
+    img: &RgbImage, segments: &mut Vec<HashSet<Point>>, dist: &ColorSpaceDistance, passes: usize,
+) {
+    for _ in 0..passes {
+        let means: Vec<Rgb<u8>> =
+            segments.iter().map(|segment| utilities::mean_color(img, segment)).collect();
+        let labels: HashMap<Point, usize> = segments
+            .iter()
+            .enumerate()
+            .flat_map(|(i, segment)| segment.iter().map(move |point| (*point, i)))
+            .collect();
+        let mut moves = vec![];
+        for (i, segment) in segments.iter().enumerate() {
+            for point in segment {
+                let pixel = point.get_pixel(img);
+                let mut best = (i, dist(pixel, &means[i]));
+                for dx in -REFINEMENT_RADIUS..=REFINEMENT_RADIUS {
+                    for dy in -REFINEMENT_RADIUS..=REFINEMENT_RADIUS {
+                        if let Some(&j) = labels.get(&(*point + Point { x: dx, y: dy })) {
+                            let distance = dist(pixel, &means[j]);
+                            if distance < best.1 {
+                                best = (j, distance);
+                            }
+                        }
+                    }
+                }
+                if best.0 != i {
+                    moves.push((*point, i, best.0));
+                }
+            }
+        }
+        if moves.is_empty() {
+            return;
+        }
+        for (point, from, to) in moves {
+            if segments[from].len() > 1 {
+                segments[from].remove(&point);
+                segments[to].insert(point);
+            }
+        }
+    }
+}
+
+/// Colors every segment with the unique color of its index.
+pub fn recolor_segments(img: &mut RgbImage, segments: &Vec<HashSet<Point>>) {
+    for (i, segment) in segments.iter().enumerate() {
+        let color = utilities::generate_unique_color(i);
+        segment.iter().for_each(|point| point.put_pixel(img, color));
+    }
+}
+
+/// Groups the pixels of a label image by their color.
+/// Segments are ordered by the first occurrence of their color in raster order.
+pub fn segments_from_labels(labels: &RgbImage) -> Vec<HashSet<Point>> {
+    let mut indices = HashMap::new();
+    let mut segments: Vec<HashSet<Point>> = vec![];
+    for (x, y, pixel) in labels.enumerate_pixels() {
+        let index = *indices.entry(*pixel).or_insert_with(|| {
+            segments.push(HashSet::new());
+            segments.len() - 1
+        });
+        segments[index].insert((x, y).into());
+    }
+    return segments;
+}
+
+/// Contour image of the segments, the inverse of `extract_segments`.
+/// Pixels of a segment with an orthogonal neighbour in a different segment are marked black,
+/// as are pixels not in any segment, which were part of the contour the segments came from.
+/// All other pixels are white, so a single segment covering the image has no contour at all,
+/// while an image without segments is all contour.
+pub fn segments_to_contour(segments: &Vec<HashSet<Point>>, width: u32, height: u32) -> RgbImage {
+    let labels = label_pixels(segments, width, height);
+    let label = |point: Point| {
+        return point
+            .within(width, height, false)
+            .and_then(|p| labels[(p.y * width as i64 + p.x) as usize]);
+    };
+    return RgbImage::from_fn(width, height, |x, y| {
+        let point = Point::from((x, y));
+        let own = label(point);
+        let is_boundary = own.is_none()
+            || point
+                .iterate_connected(Connectivity::Four)
+                .any(|neighbour| label(neighbour).map_or(false, |other| Some(other) != own));
+        return if is_boundary {
+            Rgb([0, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        };
+    });
+}
+
+/// Moore neighbourhood in clockwise order, starting at the top.
+const CLOCKWISE: [Point; 8] = [
+    Point { x: 0, y: -1 },
+    Point { x: 1, y: -1 },
+    Point { x: 1, y: 0 },
+    Point { x: 1, y: 1 },
+    Point { x: 0, y: 1 },
+    Point { x: -1, y: 1 },
+    Point { x: -1, y: 0 },
+    Point { x: -1, y: -1 },
+];
+
+/// Outer boundary of the segment as a closed polygon, its pixels ordered clockwise.
+/// Traces the boundary with Moore-neighbour tracing, starting at its first pixel in raster order,
+/// which is not repeated at the end. Pixels where the boundary touches itself,
+/// e.g. along lines one pixel wide, occur once for each time the boundary passes them.
+/// Holes are ignored, as are parts of the segment not 8-connected to the first pixel.
+pub fn segment_boundary_polygon(segment: &HashSet<Point>, width: u32, height: u32) -> Vec<Point> {
+    let contains = |point: Point| {
+        return point.within(width, height, false).map_or(false, |p| segment.contains(&p));
+    };
+    let start = match segment.iter().filter(|&&p| contains(p)).min_by_key(|p| (p.y, p.x)) {
+        Some(start) => *start,
+        None => return vec![],
+    };
+    let direction_to = |from: Point, to: Point| {
+        let offset = Point { x: to.x - from.x, y: to.y - from.y };
+        return CLOCKWISE.iter().position(|&dir| dir == offset).unwrap();
+    };
+    // Next boundary pixel clockwise around the current one, starting after the backtrack,
+    // which is the last pixel outside of the segment that was checked.
+    let next = |current: Point, backtrack: Point| {
+        let first = direction_to(current, backtrack);
+        return (1..=8).map(|i| (first + i) % 8).find_map(|i| {
+            let candidate = current + CLOCKWISE[i];
+            let outside = current + CLOCKWISE[(i + 7) % 8];
+            return contains(candidate).then_some((candidate, outside));
+        });
+    };
+    // Nothing lies in the row above or to the left of the first pixel in raster order.
+    let first_step = match next(start, start + CLOCKWISE[6]) {
+        Some(step) => step,
+        None => return vec![start],
+    };
+    let mut polygon = vec![start];
+    let (mut current, mut backtrack) = first_step;
+    // The boundary is closed once it leaves the start in the same way as at the beginning.
+    while let Some(step) = next(current, backtrack) {
+        if current == start && step == first_step {
             break;
         }
-        // Fill in every connected pixel with random color.
-        let (sx, sy, _) = blank.unwrap();
-        let color = utilities::generate_unique_color(segments.len());
-        segments.push(utilities::fill_connected(&mut p, &color, sx, sy));
+        polygon.push(current);
+        (current, backtrack) = step;
+    }
+    return polygon;
+}
+
+/// Points of the image matching the given predicate, e.g. the pixels of a boundary.
+pub fn select_points<F>(img: &RgbImage, predicate: F) -> HashSet<Point>
+where
+    F: Fn(&Rgb<u8>) -> bool,
+{
+    return img
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| predicate(pixel))
+        .map(|(x, y, _)| Point::from((x, y)))
+        .collect();
+}
+
+/// Fraction of the boundary points that lie within the tolerance distance
+/// of any of the reference boundary points.
+/// An empty boundary is matched completely.
+pub fn boundary_match(
+    boundary: &HashSet<Point>, reference: &HashSet<Point>, tolerance: u32,
+) -> f64 {
+    if boundary.is_empty() {
+        return 1.0;
+    }
+    let radius = tolerance as i64;
+    let hits = boundary
+        .iter()
+        .filter(|point| {
+            (-radius..=radius).any(|dx| {
+                (-radius..=radius).any(|dy| {
+                    let candidate = **point + Point { x: dx, y: dy };
+                    point.euclidean_distance(&candidate) <= tolerance as f64
+                        && reference.contains(&candidate)
+                })
+            })
+        })
+        .count();
+    return hits as f64 / boundary.len() as f64;
+}
+
+/// Precision, recall and F-measure of boundaries compared to ground-truth boundaries.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundaryScores {
+    pub precision: f64,
+    pub recall: f64,
+    pub f_measure: f64,
+}
+
+impl BoundaryScores {
+    /// Boundary pixels within the tolerance distance of each other count as hits,
+    /// like in the BSDS benchmark.
+    pub fn new(boundary: &HashSet<Point>, ground_truth: &HashSet<Point>, tolerance: u32) -> Self {
+        let precision = boundary_match(boundary, ground_truth, tolerance);
+        let recall = boundary_match(ground_truth, boundary, tolerance);
+        let mut f_measure = 0.0;
+        if precision + recall > 0.0 {
+            f_measure = 2.0 * precision * recall / (precision + recall);
+        }
+        return Self { precision, recall, f_measure };
+    }
+}
+
+/// Agreement between two segmentations of the same image.
+/// Only pixels belonging to a segment in both segmentations are considered.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentationAgreement {
+    /// Fraction of pixel pairs on which both segmentations agree,
+    /// i.e. whether the two pixels belong to the same segment or not.
+    pub rand_index: f64,
+    /// Information lost and gained when changing from one segmentation to the other, in nats.
+    /// Zero for identical segmentations.
+    pub variation_of_information: f64,
+    /// Segmentation covering of the first by the second segmentation:
+    /// Each segment of the first is matched with the segment of the second of highest IoU,
+    /// which are averaged weighted by the size of the segments.
+    pub covering: f64,
+    /// Segmentation covering of the second by the first segmentation, see `covering`.
+    pub reverse_covering: f64,
+}
+
+impl SegmentationAgreement {
+    pub fn new(a: &Vec<HashSet<Point>>, b: &Vec<HashSet<Point>>) -> Self {
+        let labels_b: HashMap<Point, usize> = b
+            .iter()
+            .enumerate()
+            .flat_map(|(j, segment)| segment.iter().map(move |point| (*point, j)))
+            .collect();
+        // Contingency table of the overlaps between segments of both segmentations.
+        let mut overlaps: HashMap<(usize, usize), f64> = HashMap::new();
+        for (i, segment) in a.iter().enumerate() {
+            for point in segment {
+                if let Some(&j) = labels_b.get(point) {
+                    *overlaps.entry((i, j)).or_insert(0.0) += 1.0;
+                }
+            }
+        }
+        let mut sizes_a = vec![0.0; a.len()];
+        let mut sizes_b = vec![0.0; b.len()];
+        for (&(i, j), &overlap) in &overlaps {
+            sizes_a[i] += overlap;
+            sizes_b[j] += overlap;
+        }
+        let total: f64 = sizes_a.iter().sum();
+        if total == 0.0 {
+            return Self {
+                rand_index: 1.0,
+                variation_of_information: 0.0,
+                covering: 1.0,
+                reverse_covering: 1.0,
+            };
+        }
+
+        let pairs = |n: f64| n * (n - 1.0) / 2.0;
+        let same_in_both: f64 = overlaps.values().map(|&n| pairs(n)).sum();
+        let same_in_a: f64 = sizes_a.iter().map(|&n| pairs(n)).sum();
+        let same_in_b: f64 = sizes_b.iter().map(|&n| pairs(n)).sum();
+        let mut rand_index = 1.0;
+        if total > 1.0 {
+            let disagreements = same_in_a + same_in_b - 2.0 * same_in_both;
+            rand_index = 1.0 - disagreements / pairs(total);
+        }
+
+        let variation_of_information = overlaps
+            .iter()
+            .map(|(&(i, j), &n)| n / total * ((sizes_a[i] / n).ln() + (sizes_b[j] / n).ln()))
+            .sum();
+
+        let mut best_a = vec![0.0f64; a.len()];
+        let mut best_b = vec![0.0f64; b.len()];
+        for (&(i, j), &n) in &overlaps {
+            let iou = n / (sizes_a[i] + sizes_b[j] - n);
+            best_a[i] = best_a[i].max(iou);
+            best_b[j] = best_b[j].max(iou);
+        }
+        let covering = sizes_a.iter().zip(&best_a).map(|(s, iou)| s * iou).sum::<f64>() / total;
+        let reverse_covering =
+            sizes_b.iter().zip(&best_b).map(|(s, iou)| s * iou).sum::<f64>() / total;
+
+        return Self { rand_index, variation_of_information, covering, reverse_covering };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+    #[test]
+    fn banded_labeling_matches_sequential_labeling() {
+        let mut rng = SmallRng::seed_from_u64(814);
+        let mut most_components = 0;
+        for _ in 0..20 {
+            let (width, height) = (rng.gen_range(1..30), rng.gen_range(1..70));
+            let density = rng.gen_range(0.3..0.7);
+            let mask = RgbImage::from_fn(width, height, |_, _| {
+                return if rng.gen_bool(density) { WHITE } else { BLACK };
+            });
+            for connectivity in [Connectivity::Four, Connectivity::Eight] {
+                for wrap in [false, true] {
+                    let sequential =
+                        banded_connected_components(&mask, WHITE, connectivity, wrap, 1);
+                    most_components = most_components.max(sequential.len());
+                    for bands in [2, 3, 7, height] {
+                        let banded =
+                            banded_connected_components(&mask, WHITE, connectivity, wrap, bands);
+                        assert_eq!(sequential, banded);
+                    }
+                }
+            }
+        }
+        // Only images of many regions make for a meaningful comparison.
+        assert!(most_components > 20);
+    }
+
+    #[test]
+    fn checkerboard_contour_separates_under_four_connectivity() {
+        let contour = RgbImage::from_fn(4, 4, |x, y| if (x + y) % 2 == 0 { WHITE } else { BLACK });
+        let (_, segments) = extract_segments(&contour, Connectivity::Four, false);
+        assert_eq!(segments.len(), 8);
+        assert!(segments.iter().all(|segment| segment.len() == 1));
+        let (_, segments) = extract_segments(&contour, Connectivity::Eight, false);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].len(), 8);
+    }
+
+    #[test]
+    fn diagonal_contour_separates_under_four_connectivity() {
+        let contour = RgbImage::from_fn(5, 5, |x, y| if x == y { BLACK } else { WHITE });
+        let (_, segments) = extract_segments(&contour, Connectivity::Four, false);
+        assert_eq!(segments.len(), 2);
+        assert!(segments.iter().all(|segment| segment.len() == 10));
+        // Diagonal neighbours leak through the contour.
+        let (_, segments) = extract_segments(&contour, Connectivity::Eight, false);
+        assert_eq!(segments.len(), 1);
+    }
+
+    /// Segments of both in raster order, checking that labels agree with the segments.
+    fn partition(labeled: &LabeledSegments) -> Vec<HashSet<Point>> {
+        for (i, segment) in labeled.segments.iter().enumerate() {
+            assert!(segment.iter().all(|point| labeled.label(point) == Some(i)));
+        }
+        let mut segments = labeled.segments.clone();
+        sort_segments(&mut segments, SegmentOrder::Raster);
+        return segments;
+    }
+
+    #[test]
+    fn incremental_update_matches_full_extraction() {
+        let mut rng = SmallRng::seed_from_u64(628);
+        let (width, height) = (24, 18);
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            for wrap in [false, true] {
+                // Contour lines along random rows and columns, so that there are several regions.
+                let mut contour = RgbImage::from_pixel(width, height, WHITE);
+                for _ in 0..4 {
+                    let (x, y) = (rng.gen_range(0..width), rng.gen_range(0..height));
+                    (0..height).for_each(|y| contour.put_pixel(x, y, BLACK));
+                    (0..width).for_each(|x| contour.put_pixel(x, y, BLACK));
+                }
+                let mut labeled = LabeledSegments::new(contour.clone(), connectivity, wrap);
+                for _ in 0..30 {
+                    // Few changes, so that the update stays incremental.
+                    for _ in 0..rng.gen_range(1..8) {
+                        let (x, y) = (rng.gen_range(0..width), rng.gen_range(0..height));
+                        let flipped = if *contour.get_pixel(x, y) == WHITE {
+                            BLACK
+                        } else {
+                            WHITE
+                        };
+                        contour.put_pixel(x, y, flipped);
+                    }
+                    labeled.update(contour.clone());
+                    let full = LabeledSegments::new(contour.clone(), connectivity, wrap);
+                    assert_eq!(partition(&labeled), partition(&full));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn agreement_of_identical_segmentations() {
+        let segments = vec![
+            points(&[(0, 0), (1, 0)]).into_iter().collect(),
+            points(&[(0, 1), (1, 1), (2, 1)]).into_iter().collect(),
+        ];
+        let agreement = SegmentationAgreement::new(&segments, &segments);
+        assert_eq!(agreement.rand_index, 1.0);
+        assert_eq!(agreement.variation_of_information, 0.0);
+        assert_eq!(agreement.covering, 1.0);
+        assert_eq!(agreement.reverse_covering, 1.0);
+    }
+
+    #[test]
+    fn agreement_of_split_segment() {
+        let whole = vec![points(&[(0, 0), (1, 0), (2, 0), (3, 0)]).into_iter().collect()];
+        let halves = vec![
+            points(&[(0, 0), (1, 0)]).into_iter().collect(),
+            points(&[(2, 0), (3, 0)]).into_iter().collect(),
+        ];
+        let agreement = SegmentationAgreement::new(&whole, &halves);
+        // 2 of the 6 pairs of pixels are together in both.
+        assert!((agreement.rand_index - 1.0 / 3.0).abs() < 1e-9);
+        assert!((agreement.variation_of_information - 2f64.ln()).abs() < 1e-9);
+        assert_eq!(agreement.covering, 0.5);
+        assert_eq!(agreement.reverse_covering, 0.5);
+    }
+
+    #[test]
+    fn agreement_of_single_pixel() {
+        let pixel = vec![points(&[(0, 0)]).into_iter().collect()];
+        let agreement = SegmentationAgreement::new(&pixel, &pixel);
+        assert_eq!(agreement.rand_index, 1.0);
+        assert!(agreement.variation_of_information.is_finite());
+    }
+
+    fn points(coordinates: &[(i64, i64)]) -> Vec<Point> {
+        return coordinates.iter().map(|&(x, y)| Point { x, y }).collect();
+    }
+
+    #[test]
+    fn rectangle_boundary_polygon() {
+        let segment = (1..=3).flat_map(|x| (1..=2).map(move |y| Point { x, y })).collect();
+        let polygon = segment_boundary_polygon(&segment, 5, 4);
+        assert_eq!(polygon, points(&[(1, 1), (2, 1), (3, 1), (3, 2), (2, 2), (1, 2)]));
+    }
+
+    #[test]
+    fn l_shape_boundary_polygon() {
+        let segment = points(&[(0, 0), (0, 1), (0, 2), (1, 2), (2, 2)]).into_iter().collect();
+        let polygon = segment_boundary_polygon(&segment, 3, 3);
+        // The arms are one pixel wide, so the boundary passes them on both sides.
+        let expected = points(&[(0, 0), (0, 1), (1, 2), (2, 2), (1, 2), (0, 2), (0, 1)]);
+        assert_eq!(polygon, expected);
     }
-    return (p, segments);
 }
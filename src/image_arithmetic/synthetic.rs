@@ -0,0 +1,62 @@
+//! Synthetic images with known structure, e.g. for demonstrations.
+
+use super::{generate_color, Point};
+use image::{Rgb, RgbImage};
+
+/// Kinds of images that can be generated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyntheticKind {
+    /// Alternating black and white squares.
+    Checkerboard,
+    /// Smooth transition between colors without any edges.
+    Gradient,
+    /// Randomly placed discs of different colors on a gray background.
+    Blobs,
+}
+
+impl SyntheticKind {
+    pub fn generate<R: rand::Rng>(self, rng: &mut R, width: u32, height: u32) -> RgbImage {
+        return match self {
+            Self::Checkerboard => checkerboard(width, height, (width.min(height) / 8).max(1)),
+            Self::Gradient => gradient(width, height),
+            Self::Blobs => blobs(rng, width, height, 6),
+        };
+    }
+}
+
+pub fn checkerboard(width: u32, height: u32, cell_size: u32) -> RgbImage {
+    return RgbImage::from_fn(width, height, |x, y| {
+        if (x / cell_size + y / cell_size) % 2 == 0 {
+            return Rgb([255, 255, 255]);
+        }
+        return Rgb([0, 0, 0]);
+    });
+}
+
+/// Red increases from left to right, blue from top to bottom.
+pub fn gradient(width: u32, height: u32) -> RgbImage {
+    return RgbImage::from_fn(width, height, |x, y| {
+        Rgb([
+            (x * 255 / (width - 1).max(1)) as u8,
+            64,
+            (y * 255 / (height - 1).max(1)) as u8,
+        ])
+    });
+}
+
+/// Later discs are drawn on top of earlier ones.
+pub fn blobs<R: rand::Rng>(rng: &mut R, width: u32, height: u32, count: usize) -> RgbImage {
+    let mut img = RgbImage::from_pixel(width, height, Rgb([128, 128, 128]));
+    let max_radius = (width.min(height) / 4).max(2);
+    for i in 0..count {
+        let center = Point::spawn(rng, width, height);
+        let radius = rng.gen_range(max_radius / 2..=max_radius) as f64;
+        let color = generate_color(i);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            if Point::from((x, y)).euclidean_distance(&center) <= radius {
+                *pixel = color;
+            }
+        }
+    }
+    return img;
+}
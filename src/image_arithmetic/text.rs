@@ -0,0 +1,98 @@
+//! Minimal bitmap font to label images without external fonts.
+
+use std::ops::DerefMut;
+
+use image::{imageops, ImageBuffer, Pixel, Rgb, RgbImage};
+
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+/// Rows of the glyph from top to bottom, the highest of the three bits is the leftmost pixel.
+/// Unknown characters are rendered blank.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    return match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0b000; GLYPH_HEIGHT as usize],
+    };
+}
+
+/// Width in pixels of the given text when drawn with `draw_text`.
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    return text.chars().count() as u32 * (GLYPH_WIDTH + 1) * scale;
+}
+
+/// Draws the text with its top-left corner at the given position.
+/// Pixels outside of the image are skipped.
+pub fn draw_text<P, C>(
+    img: &mut ImageBuffer<P, C>, text: &str, x: u32, y: u32, scale: u32, color: P,
+) where
+    P: Pixel,
+    C: DerefMut<Target = [P::Subpixel]>,
+{
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dx in 0..scale {
+                    for dy in 0..scale {
+                        let px = left + column * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+                        if let Some(pixel) = img.get_pixel_mut_checked(px, py) {
+                            *pixel = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Appends a strip below the image containing the caption.
+pub fn add_caption(img: &RgbImage, caption: &str) -> RgbImage {
+    let scale = 2;
+    let (width, height) = img.dimensions();
+    let mut canvas = RgbImage::new(width, height + (GLYPH_HEIGHT + 2) * scale);
+    imageops::replace(&mut canvas, img, 0, 0);
+    draw_text(&mut canvas, caption, scale, height + scale, scale, Rgb([255, 255, 255]));
+    return canvas;
+}
@@ -1,13 +1,189 @@
-use image::Primitive;
+use image::{ImageBuffer, Pixel, Primitive};
 
 pub trait ArithmeticImage<N: Primitive>: Sized {
     fn max(&self) -> N;
     fn min(&self) -> N;
+    fn mean(&self) -> N;
+    /// Shannon entropy in nats of the values, taken as a distribution after normalizing their sum.
+    /// Low entropy means that the values are concentrated on few pixels.
+    fn entropy(&self) -> N;
     fn normalize(&mut self);
+    fn normalize_sum(&mut self);
     fn binarize(&mut self, threshold: N);
     fn clamp(&mut self, threshold: N);
+    /// Panics if the images differ in their dimensions.
     fn add(&mut self, other: &Self);
     fn add_scalar(&mut self, num: N);
+    /// Panics if the images differ in their dimensions.
     fn mul(&mut self, other: &Self);
     fn mul_scalar(&mut self, num: N);
+    /// Same as `clamp` at `fraction` of the maximum followed by `normalize`,
+    /// but in two passes over the image instead of four.
+    fn clamp_normalize(&mut self, fraction: N);
+    /// Same as `normalize` followed by `mul_scalar`, but in two passes instead of three.
+    fn normalize_scaled(&mut self, num: N);
+    /// Same as `normalize_sum` followed by `mul_scalar`, but in two passes instead of three.
+    fn normalize_sum_scaled(&mut self, num: N);
+    /// Same as `add` of `other` multiplied by `factor` followed by `add_scalar` of `offset`,
+    /// but in a single pass and without modifying `other`.
+    /// Panics if the images differ in their dimensions.
+    fn add_scaled(&mut self, other: &Self, factor: N, offset: N);
 }
+
+/// Implements `ArithmeticImage` for buffers of any pixel type with the given float subpixels,
+/// e.g. `Luma<f32>` pheromones, but also `La<f32>` or `Rgb<f32>` for multi-channel experiments.
+/// All operations work on the individual subpixels,
+/// so the channels of a pixel are treated like separate pixels.
+/// In particular, normalization and binarization use the maximum across all channels.
+macro_rules! impl_float_arithmetic_image {
+    ($float:ty) => {
+        impl<P: Pixel<Subpixel = $float>> ArithmeticImage<$float> for ImageBuffer<P, Vec<$float>> {
+            fn max(&self) -> $float {
+                return self.as_raw().iter().fold(0.0, |a: $float, &b| a.max(b));
+            }
+
+            fn min(&self) -> $float {
+                return self.as_raw().iter().fold(<$float>::INFINITY, |a: $float, &b| a.min(b));
+            }
+
+            fn mean(&self) -> $float {
+                return self.as_raw().iter().sum::<$float>() / self.as_raw().len().max(1) as $float;
+            }
+
+            fn entropy(&self) -> $float {
+                let sum: $float = self.as_raw().iter().sum();
+                if sum <= 0.0 {
+                    return 0.0;
+                }
+                return self
+                    .as_raw()
+                    .iter()
+                    .filter(|&&value| value > 0.0)
+                    .map(|value| -(value / sum) * (value / sum).ln())
+                    .sum();
+            }
+
+            fn normalize(&mut self) {
+                let max = self.max();
+                if max != 0.0 && max != 1.0 {
+                    for value in self.iter_mut() {
+                        *value /= max;
+                    }
+                }
+            }
+
+            fn normalize_sum(&mut self) {
+                let sum: $float = self.as_raw().iter().sum();
+                if sum != 0.0 && sum != 1.0 {
+                    for value in self.iter_mut() {
+                        *value /= sum;
+                    }
+                }
+            }
+
+            fn binarize(&mut self, threshold: $float) {
+                self.normalize();
+                for value in self.iter_mut() {
+                    *value = (*value > threshold) as u8 as $float;
+                }
+            }
+
+            fn clamp(&mut self, threshold: $float) {
+                for value in self.iter_mut() {
+                    *value = threshold.min(*value);
+                }
+            }
+
+            fn add(&mut self, other: &Self) {
+                assert_eq!(
+                    self.dimensions(),
+                    other.dimensions(),
+                    "Images must have the same dimensions to be added!"
+                );
+                for (value, other) in self.iter_mut().zip(other.iter()) {
+                    *value += other;
+                }
+            }
+
+            fn add_scalar(&mut self, num: $float) {
+                for value in self.iter_mut() {
+                    *value += num;
+                    if *value < 0.0 {
+                        *value = 0.0;
+                    }
+                }
+            }
+
+            fn mul(&mut self, other: &Self) {
+                assert_eq!(
+                    self.dimensions(),
+                    other.dimensions(),
+                    "Images must have the same dimensions to be multiplied!"
+                );
+                for (value, other) in self.iter_mut().zip(other.iter()) {
+                    *value *= other;
+                }
+            }
+
+            fn mul_scalar(&mut self, num: $float) {
+                for value in self.iter_mut() {
+                    *value *= num;
+                }
+            }
+
+            fn clamp_normalize(&mut self, fraction: $float) {
+                let max = self.max();
+                let threshold = max * fraction;
+                // The maximum after clamping, as `max` would find it.
+                let max = threshold.min(max).max(0.0);
+                let divide = max != 0.0 && max != 1.0;
+                for value in self.iter_mut() {
+                    *value = threshold.min(*value);
+                    if divide {
+                        *value /= max;
+                    }
+                }
+            }
+
+            fn normalize_scaled(&mut self, num: $float) {
+                let max = self.max();
+                let divide = max != 0.0 && max != 1.0;
+                for value in self.iter_mut() {
+                    if divide {
+                        *value /= max;
+                    }
+                    *value *= num;
+                }
+            }
+
+            fn normalize_sum_scaled(&mut self, num: $float) {
+                let sum: $float = self.as_raw().iter().sum();
+                let divide = sum != 0.0 && sum != 1.0;
+                for value in self.iter_mut() {
+                    if divide {
+                        *value /= sum;
+                    }
+                    *value *= num;
+                }
+            }
+
+            fn add_scaled(&mut self, other: &Self, factor: $float, offset: $float) {
+                assert_eq!(
+                    self.dimensions(),
+                    other.dimensions(),
+                    "Images must have the same dimensions to be added!"
+                );
+                for (value, other) in self.iter_mut().zip(other.iter()) {
+                    *value += other * factor;
+                    *value += offset;
+                    if *value < 0.0 {
+                        *value = 0.0;
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_float_arithmetic_image!(f32);
+impl_float_arithmetic_image!(f64);
@@ -4,7 +4,7 @@ use std::ops::{Add, Deref, DerefMut};
 use image::{ImageBuffer, Pixel, Rgb};
 use rand::seq::IteratorRandom;
 
-pub type ColorSpaceDistance = dyn Fn(&Rgb<u8>, &Rgb<u8>) -> f64;
+pub type ColorSpaceDistance<T = u8> = dyn Fn(&Rgb<T>, &Rgb<T>) -> f64 + Send + Sync;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Point {
@@ -20,6 +20,21 @@ impl Point {
         };
     }
 
+    /// Spawns a point at most `distance` away along each axis from the center.
+    pub fn spawn_near<R: rand::Rng>(
+        rng: &mut R, center: Point, distance: u32, width: u32, height: u32,
+    ) -> Self {
+        let distance = distance as i64;
+        return Self {
+            x: (0.max(center.x - distance)..=(width as i64 - 1).min(center.x + distance))
+                .choose(rng)
+                .unwrap(),
+            y: (0.max(center.y - distance)..=(height as i64 - 1).min(center.y + distance))
+                .choose(rng)
+                .unwrap(),
+        };
+    }
+
     pub const fn neighbourhood_directions() -> &'static [Self] {
         return &[
             Self { x: 1, y: 0 },
@@ -37,6 +52,10 @@ impl Point {
         return Self::neighbourhood_directions().iter().map(move |dir| self + *dir);
     }
 
+    pub fn iterate_connected(self, connectivity: Connectivity) -> impl Iterator<Item = Point> {
+        return connectivity.directions().iter().map(move |dir| self + *dir);
+    }
+
     pub fn is_within_rectangle(self, a: &Self, b: &Self) -> bool {
         let min_x = a.x.min(b.x);
         let max_x = a.x.max(b.x);
@@ -45,6 +64,60 @@ impl Point {
         return self.x >= min_x && self.x <= max_x && self.y >= min_y && self.y <= max_y;
     }
 
+    /// Wraps the point around the edges of an image of the given dimensions,
+    /// as if opposite edges were connected like on a torus.
+    pub fn wrap(self, width: u32, height: u32) -> Self {
+        return Self {
+            x: self.x.rem_euclid(width as i64),
+            y: self.y.rem_euclid(height as i64),
+        };
+    }
+
+    /// The pixel this point refers to in an image of the given dimensions.
+    /// Points outside of the image are wrapped around its edges if `wrap` is set,
+    /// otherwise there is no such pixel.
+    pub fn within(self, width: u32, height: u32, wrap: bool) -> Option<Self> {
+        if wrap {
+            return Some(self.wrap(width, height));
+        }
+        let corner_b = Self { x: width as i64 - 1, y: height as i64 - 1 };
+        if self.is_within_rectangle(&Self { x: 0, y: 0 }, &corner_b) {
+            return Some(self);
+        }
+        return None;
+    }
+
+    /// Pixels on the line from this point to the other, including both ends.
+    /// Uses Bresenham's algorithm, so consecutive points are 8-connected.
+    pub fn line_to(self, other: Self) -> impl Iterator<Item = Point> {
+        let dx = (other.x - self.x).abs();
+        let dy = -(other.y - self.y).abs();
+        let step_x = if self.x < other.x { 1 } else { -1 };
+        let step_y = if self.y < other.y { 1 } else { -1 };
+        let mut error = dx + dy;
+        let mut current = Some(self);
+        return std::iter::from_fn(move || {
+            let point = current?;
+            if point == other {
+                current = None;
+                return Some(point);
+            }
+            let mut next = point;
+            // Both steps are decided on the error before either of them is taken.
+            let doubled_error = 2 * error;
+            if doubled_error >= dy {
+                error += dy;
+                next.x += step_x;
+            }
+            if doubled_error <= dx {
+                error += dx;
+                next.y += step_y;
+            }
+            current = Some(next);
+            return Some(point);
+        });
+    }
+
     fn square(x: i64) -> f64 {
         (x as f64) * (x as f64)
     }
@@ -57,6 +130,16 @@ impl Point {
         return self.euclidean_squared_distance(other).sqrt();
     }
 
+    /// Euclidean distance on an image of the given dimensions whose edges wrap around,
+    /// taking the shorter way along each axis.
+    pub fn wrapped_euclidean_distance(self, other: &Self, width: u32, height: u32) -> f64 {
+        let dx = (other.x - self.x).rem_euclid(width as i64);
+        let dy = (other.y - self.y).rem_euclid(height as i64);
+        return (Self::square(dx.min(width as i64 - dx))
+            + Self::square(dy.min(height as i64 - dy)))
+        .sqrt();
+    }
+
     pub fn manhattan_distance(self, other: &Self) -> i64 {
         return (other.x - self.x).abs() + (other.y - self.y).abs();
     }
@@ -87,6 +170,34 @@ impl Point {
     }
 }
 
+/// Which neighbours of a pixel are considered connected to it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Connectivity {
+    /// Only orthogonal neighbours.
+    Four,
+    /// Orthogonal and diagonal neighbours.
+    Eight,
+}
+
+impl Connectivity {
+    pub fn directions(self) -> &'static [Point] {
+        let directions = Point::neighbourhood_directions();
+        return match self {
+            Self::Four => &directions[..4],
+            Self::Eight => directions,
+        };
+    }
+
+    /// Regions bounded by contours of one connectivity
+    /// need to be filled with the other, so that they cannot leak through the contour.
+    pub fn complement(self) -> Self {
+        return match self {
+            Self::Four => Self::Eight,
+            Self::Eight => Self::Four,
+        };
+    }
+}
+
 impl From<(u32, u32)> for Point {
     fn from(value: (u32, u32)) -> Self {
         return Self { x: value.0 as i64, y: value.1 as i64 };
@@ -108,3 +219,60 @@ impl Add for Point {
         return Self { x: self.x + other.x, y: self.y + other.y };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(from: (i64, i64), to: (i64, i64)) -> Vec<(i64, i64)> {
+        let (from, to) = (Point { x: from.0, y: from.1 }, Point { x: to.0, y: to.1 });
+        return from.line_to(to).map(|p| (p.x, p.y)).collect();
+    }
+
+    #[test]
+    fn line_horizontal() {
+        assert_eq!(line((0, 0), (3, 0)), vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(line((3, 2), (1, 2)), vec![(3, 2), (2, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn line_vertical() {
+        assert_eq!(line((1, 0), (1, 3)), vec![(1, 0), (1, 1), (1, 2), (1, 3)]);
+        assert_eq!(line((0, 2), (0, 0)), vec![(0, 2), (0, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn line_diagonal() {
+        assert_eq!(line((0, 0), (3, 3)), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+        assert_eq!(line((2, 0), (0, 2)), vec![(2, 0), (1, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn line_shallow() {
+        assert_eq!(line((0, 0), (2, 1)), vec![(0, 0), (1, 1), (2, 1)]);
+        assert_eq!(line((0, 0), (3, 2)), vec![(0, 0), (1, 1), (2, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn line_steep() {
+        assert_eq!(line((0, 0), (1, 3)), vec![(0, 0), (0, 1), (1, 2), (1, 3)]);
+        assert_eq!(line((1, 4), (0, 0)), vec![(1, 4), (1, 3), (0, 2), (0, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn line_single_point() {
+        assert_eq!(line((2, 5), (2, 5)), vec![(2, 5)]);
+    }
+
+    #[test]
+    fn line_is_eight_connected() {
+        for (to_x, to_y) in [(7, 2), (-5, 9), (4, -11), (-8, -3)] {
+            let points = line((0, 0), (to_x, to_y));
+            assert_eq!(points.len() as i64, 1 + to_x.abs().max(to_y.abs()));
+            assert_eq!(points.last(), Some(&(to_x, to_y)));
+            for pair in points.windows(2) {
+                assert!((pair[1].0 - pair[0].0).abs() <= 1 && (pair[1].1 - pair[0].1).abs() <= 1);
+            }
+        }
+    }
+}
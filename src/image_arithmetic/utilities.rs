@@ -1,8 +1,13 @@
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::BufWriter;
+use std::path::Path;
 
 use super::Point;
-use image::{ImageBuffer, Pixel, Rgb};
-use num_traits::{FromPrimitive, ToPrimitive};
+use image::{ImageBuffer, Pixel, Rgb, RgbImage};
+use num_traits::{Bounded, FromPrimitive, ToPrimitive};
 
 pub fn generate_color(num: usize) -> Rgb<u8> {
     let i = num + 1;
@@ -17,11 +22,26 @@ pub fn generate_unique_color(num: usize) -> Rgb<u8> {
     return generate_color(num);
 }
 
+/// Color derived from the centroid of the points, rounded to the nearest pixel,
+/// so that a region found in different runs gets the same color regardless of its index.
+pub fn generate_stable_color(points: &HashSet<Point>) -> Rgb<u8> {
+    let count = points.len().max(1) as f64;
+    let (sum_x, sum_y) = points
+        .iter()
+        .fold((0.0, 0.0), |(sum_x, sum_y), point| (sum_x + point.x as f64, sum_y + point.y as f64));
+    let mut hasher = DefaultHasher::new();
+    ((sum_x / count).round() as i64, (sum_y / count).round() as i64).hash(&mut hasher);
+    let [r, g, b, ..] = hasher.finish().to_le_bytes();
+    return Rgb([r, g, b]);
+}
+
+/// Mean color of the given points.
+/// Returns a pixel with all channels set to zero if there are no points.
 pub fn mean_color<P, C>(img: &ImageBuffer<P, C>, points: &HashSet<Point>) -> P
 where
     P: Pixel,
     C: std::ops::DerefMut<Target = [P::Subpixel]>,
-    P::Subpixel: FromPrimitive,
+    P::Subpixel: FromPrimitive + Bounded,
 {
     let summed = points.iter().map(|point| point.get_pixel(img)).fold(
         vec![0 as f64; P::CHANNEL_COUNT as usize],
@@ -32,46 +52,68 @@ where
             acc
         },
     );
+    // Guard against rounding errors leaving the range of the subpixel type.
+    let lower = P::Subpixel::min_value().to_f64().unwrap();
+    let upper = P::Subpixel::max_value().to_f64().unwrap();
+    let count = points.len().max(1) as f64;
     let mut mean = vec![];
     for value in summed.into_iter() {
-        mean.push(P::Subpixel::from_f64(value / points.len() as f64).unwrap());
+        mean.push(P::Subpixel::from_f64((value / count).clamp(lower, upper)).unwrap());
     }
     return *P::from_slice(mean.as_slice());
 }
 
-pub fn fill_connected<P, C>(
-    img: &mut ImageBuffer<P, C>, color: &P, sx: u32, sy: u32,
-) -> HashSet<Point>
-where
-    P: Pixel + std::cmp::PartialEq,
-    C: std::ops::DerefMut<Target = [P::Subpixel]>,
-{
-    let mut filled = HashSet::new();
-    let mut queued = vec![];
-    let original_color = img.get_pixel(sx, sy).clone();
-    queued.push(Point::from((sx, sy)));
-    while !queued.is_empty() {
-        let point = queued.pop().unwrap();
-        filled.insert(point);
-        let (x, y) = point.try_into().unwrap();
-        img.put_pixel(x, y, *color);
-        for dx in [-1, 0, 1] {
-            for dy in [-1, 0, 1] {
-                if dx == dy || dx == -dy {
-                    continue;
-                }
-                let nx = 0.max(x as i64 + dx) as u32;
-                let ny = 0.max(y as i64 + dy) as u32;
-                let neighbour = img.get_pixel_checked(nx, ny);
-                if neighbour.is_none() {
-                    continue;
-                }
-                let npoint = Point::from((nx, ny));
-                if neighbour.unwrap() == &original_color && !filled.contains(&npoint) {
-                    queued.push(npoint);
-                }
-            }
-        }
+/// Most frequent color of the given points, ties are broken by the smallest channel values.
+/// Returns black if there are no points.
+pub fn mode_color(img: &RgbImage, points: &HashSet<Point>) -> Rgb<u8> {
+    let mut frequencies = HashMap::new();
+    for point in points {
+        *frequencies.entry(point.get_pixel(img).0).or_insert(0) += 1;
+    }
+    let mode = frequencies
+        .into_iter()
+        .max_by_key(|&(color, count)| (count, std::cmp::Reverse(color)))
+        .map_or([0, 0, 0], |(color, _)| color);
+    return Rgb(mode);
+}
+
+/// Saves the image as PNG, storing the given pairs of keywords and values as text chunks.
+/// Keywords must consist of 1 to 79 Latin-1 characters.
+pub fn save_png_with_text<Q: AsRef<Path>>(
+    img: &RgbImage, path: Q, text: &[(&str, String)],
+) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, img.width(), img.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder.add_text_chunk(keyword.to_string(), value.clone())?;
+    }
+    return encoder.write_header()?.write_image_data(img.as_raw());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_color_of_single_pixel() {
+        let mut img = RgbImage::new(3, 3);
+        img.put_pixel(1, 2, Rgb([12, 200, 255]));
+        let points = HashSet::from([Point { x: 1, y: 2 }]);
+        assert_eq!(mean_color(&img, &points), Rgb([12, 200, 255]));
+    }
+
+    #[test]
+    fn mean_color_of_white_image() {
+        let img = RgbImage::from_pixel(7, 5, Rgb([255, 255, 255]));
+        let points = (0..7).flat_map(|x| (0..5).map(move |y| Point { x, y })).collect();
+        assert_eq!(mean_color(&img, &points), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn mean_color_without_points() {
+        let img = RgbImage::from_pixel(2, 2, Rgb([255, 255, 255]));
+        assert_eq!(mean_color(&img, &HashSet::new()), Rgb([0, 0, 0]));
     }
-    return filled;
 }
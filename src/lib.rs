@@ -0,0 +1,8 @@
+//! Image segmentation using an ant-colony multi-objective optimization algorithm.
+//! The binary in `main.rs` is a command line interface to these modules.
+
+pub mod image_ants;
+pub mod image_arithmetic;
+pub mod pareto_pheromones;
+pub mod segment_generation;
+pub mod termination;
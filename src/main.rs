@@ -1,19 +1,32 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::io::Write;
+use std::ops::RangeInclusive;
 use std::path;
 use std::process;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use ant_image_seg::image_arithmetic::{
+    color_distances, quantize, segments, synthetic, text, ColorSpaceDistance, Point,
+};
+use ant_image_seg::termination::TerminationCriterion;
+use ant_image_seg::{
+    image_ants, image_arithmetic, pareto_pheromones, segment_generation, termination,
+};
+use image::codecs::jpeg::JpegEncoder;
 use image::io::Reader as ImageReader;
+use image::{imageops, DynamicImage, ImageError, ImageFormat, Rgb, RgbImage};
+use log::{debug, error, info, warn};
 use pareto_front::ParetoFront;
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
-
-mod image_ants;
-#[allow(dead_code)]
-mod image_arithmetic;
-mod pareto_pheromones;
-mod segment_generation;
+use rand::{Rng, SeedableRng};
 
 static PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 
@@ -22,6 +35,10 @@ fn usage(program_name: Option<&str>) {
         "Usage: {} [options] <image-path> <results-directory>",
         program_name.unwrap_or(PACKAGE_NAME)
     );
+    println!(
+        "       {} [options] --generate KIND WxH <results-directory>",
+        program_name.unwrap_or(PACKAGE_NAME)
+    );
     println!();
     println!(
         "Run an ant-colony algorithm to find a good segmentation of \
@@ -30,33 +47,710 @@ fn usage(program_name: Option<&str>) {
     println!();
     println!("Options:");
     println!("  -h, --help          print this help page instead of regular execution");
+    println!("  -q, --quiet         only print errors and results, no progress or warnings");
     println!("  -d, --detailed      export detailed pheromone images from each intermediate step");
     println!("  -e, --eval-steps    consider each intermediate step for evaluation");
     println!("  -o, --objective M|S use either [M]ulti or [S]ingle objective optimization");
+    println!("      --objectives edge,connectivity,deviation");
+    println!("                      compare solutions by the given objectives only, in multi");
+    println!("                      objective optimization the channels of the others stay empty,");
+    println!("                      deviation has no channel, so it cannot be used alone");
     println!("  -s, --seed SEED     use the given integer as a seed, otherwise use a random one");
+    println!("                      alternatively, use a hexadecimal string of the full seed");
+    println!("      --seed-file PATH");
+    println!("                      read the seed from the file at PATH");
     println!("  -t, --timeout SECS  stop generating new solutions after SECS seconds");
     println!("  -p, --parallel NUM  run NUM threads in parallel");
+    println!("  -a, --attempts NUM  stop generating new solutions after NUM attempts");
+    println!("      --converge NUM  stop generating new solutions once the pareto front has not");
+    println!("                      changed for NUM attempts");
+    println!("      --colonies NUM  run NUM independent colonies in parallel, each with its own");
+    println!("                      seed derived from the seed of the run and its own stopping");
+    println!("                      criteria, and merge their pareto fronts, each colony uses");
+    println!("                      --parallel threads as well (default: 1)");
+    println!("      --seed-points X1,Y1;X2,Y2;...");
+    println!("                      spawn ants at the given coordinates instead of randomly");
+    println!("      --export-pheromones PATH");
+    println!("                      export raw pheromone values of each step as EXR images");
+    println!("      --connectivity-weighting I|D");
+    println!("                      weight neighbours in the connectivity measure by their");
+    println!("                      [I]ndex (default) or their geometric [D]istance");
+    println!("      --edge-weighting I|G");
+    println!("                      weight neighbours in the edge value [I]sotropically (default)");
+    println!("                      or by how well their direction agrees with the image");
+    println!("                      [G]radient, favouring contours along edges over across them");
+    println!("      --fast          skip segmentation while running the colony, lowers quality");
+    println!("      --incremental   only recompute regions touched by changed contours");
+    println!("      --wrap          treat the image as a seamless texture, connecting opposite");
+    println!("                      edges, so segmentations represent a seamless tiling as well");
+    println!("      --open-border   let segments extend to the edges of the image, instead of");
+    println!("                      closing them with a border, regions touching the edge are");
+    println!("                      kept whole, but may only be closed by contours reaching it");
+    println!("      --overlay-color R,G,B");
+    println!("                      draw contours of overlayed segments in the given color");
+    println!("      --overlay-opacity 0..255");
+    println!("                      draw contours of overlayed segments with the given opacity");
+    println!("      --evaluate LABELS");
+    println!("                      only print the objectives of the segmentation given by the");
+    println!("                      label image LABELS, no results-directory is needed");
+    println!("      --evaluate-contour CONTOUR");
+    println!("                      only print the edge value of the segmentation given by the");
+    println!("                      dark contour pixels of image CONTOUR, as in type-1 outputs,");
+    println!("                      without filling its regions, the other objectives need");
+    println!("                      regions, the border added to close segments is ignored");
+    println!("      --invert-contour");
+    println!("                      take bright pixels of CONTOUR as contour instead, as produced");
+    println!("                      by many edge detectors");
+    println!("      --compare LABELS_A LABELS_B");
+    println!("                      only print how well the segmentations given by two label");
+    println!("                      images agree, no image or results-directory is needed");
+    println!("      --stats-csv PATH");
+    println!("                      write the maximum, mean, fraction of non-zero pixels and");
+    println!("                      entropy of each pheromone channel after each step as CSV");
+    println!("      --log-json PATH append the parameters, accepted solutions, finished attempts");
+    println!("                      and warnings of the run to PATH as newline-delimited JSON,");
+    println!("                      written as they happen, so a crashed run leaves a partial log");
+    println!("      --compare-json PATH");
+    println!("                      additionally write the agreement of --compare as JSON");
+    println!("      --salient-spawn spawn ants preferably in regions with strong color gradients");
+    println!("      --transparent   keep transparency in detailed pheromone images");
+    println!("      --legend        label the channels in detailed pheromone images");
+    println!("      --clamp-fraction FRAC");
+    println!("                      cap reinforcements of global updates at FRAC of their peak");
+    println!("      --update-threshold T");
+    println!("                      threshold of the regions guiding global updates, separate");
+    println!("                      from the one of the results (default: 0.25)");
+    println!("      --threshold-sweep START,END,STEP");
+    println!("                      segment each solution at all thresholds from START to END");
+    println!("                      and keep the best one, instead of a fixed threshold of 0.33");
+    println!("      --target-segments K[,TOLERANCE]");
+    println!("                      search the threshold of each solution that segments it into");
+    println!("                      K segments, give or take TOLERANCE (default: 0), or as close");
+    println!("                      to that as possible, instead of a fixed threshold of 0.33");
+    println!("      --entropy-objective");
+    println!("                      add the color entropy within segments as a fourth objective,");
+    println!("                      favouring homogeneous segments");
+    println!("      --gt-edges GT   print boundary precision, recall and F-measure of each");
+    println!("                      solution against the bright boundary pixels of image GT");
+    println!("      --tolerance D   match boundary pixels up to D pixels apart (default: 2)");
+    println!("      --revisit-decay RATE");
+    println!("                      multiply the penalty of revisiting pixels by RATE each step,");
+    println!("                      allowing ants to re-cross older paths (default: 1)");
+    println!("      --alpha A       raise the pheromone trail of each step to the power of A,");
+    println!("                      higher values make ants follow strong trails more strictly");
+    println!("                      and 0 makes them ignore trails (default: 1)");
+    println!("      --beta B        raise the attraction of the target and of similar colors to");
+    println!("                      the power of B, 0 makes ants only follow trails (default: 1)");
+    println!("      --revisit-penalty P");
+    println!("                      take the fraction P of the weight of pixels an ant visited");
+    println!("                      before, higher values keep ants off their own path more");
+    println!("                      strongly, 1 forbids stepping back onto it while there are");
+    println!(
+        "                      other neighbours, unless --revisit-decay is set (default: 0.99)"
+    );
+    println!("      --color-divisor D");
+    println!("                      divide the weight of each step by D plus the color distance,");
+    println!("                      higher values make ants care less about color changes");
+    println!("                      (default: 128)");
+    println!("      --max-target-distance DIST");
+    println!("                      choose targets of ants at most DIST pixels away, which keeps");
+    println!("                      walks short and local");
+    println!("      --warm-restart NOISE");
+    println!("                      start each attempt after the first from the pheromones of");
+    println!("                      the solution balancing all objectives best, mixed with a");
+    println!("                      fraction NOISE in [0, 1] of fresh pheromones, instead of");
+    println!("                      starting from scratch");
+    println!("      --restart-patience K[,KEEP]");
+    println!("                      with --warm-restart, start from scratch again once K attempts");
+    println!("                      in a row did not add to the pareto front, keeping a fraction");
+    println!("                      KEEP in [0, 1] of the best pheromones (default: 0)");
+    println!("      --compact       store solutions with less memory, dropping information not");
+    println!("                      needed for the outputs (individual channels and segments)");
+    println!(
+        "      --deterministic make runs reproducible, runs single-threaded and uses the seed"
+    );
+    println!("                      0 if none is given");
+    println!("      --edge-deposit AMOUNT");
+    println!("                      pheromone deposited by ants for the edge value (default: 0.1)");
+    println!("      --connectivity-deposit AMOUNT");
+    println!("                      pheromone deposited by ants for connectivity (default: 0.01)");
+    println!("      --initial-pheromone AMOUNT");
+    println!("                      pheromone all pixels start with, so the first ants already");
+    println!("                      reinforce trails, it shrinks relative to the trails with each");
+    println!("                      global update as they are rescaled (default: 0)");
+    println!("      --region-blend FACTOR");
+    println!("                      blend colors of segments with the original image, from");
+    println!("                      0 (original) to 1 (flat colors, default)");
+    println!("      --region-color mean|mode|stable|index");
+    println!("                      color of each segment, mean: the mean color of its pixels");
+    println!("                      (default); mode: their most frequent color, which keeps the");
+    println!("                      palette of illustrations; stable: a distinct color derived");
+    println!("                      from its centroid, so that the same region has the same color");
+    println!("                      across runs, e.g. to compare parameter sweeps; index: a");
+    println!("                      distinct color of its index, see --segment-order");
+    println!("      --segment-order raster|size");
+    println!("                      index segments by their top-left-most pixel (default) or by");
+    println!("                      decreasing size, so that large segments keep their index and");
+    println!("                      color if contours differ only slightly between runs");
+    println!("      --stall-window STEPS");
+    println!("                      stop ants early that did not get closer to their target");
+    println!("                      within the last STEPS steps");
+    println!("      --ant-time-budget MS");
+    println!("                      stop each ant after walking for MS milliseconds, so that");
+    println!("                      slow ants cannot stall the others, makes results depend on");
+    println!("                      the speed of the machine (default: unlimited)");
+    println!("      --preview SCALE");
+    println!("                      start each attempt with a coarse pass on the image scaled");
+    println!("                      down by SCALE, with proportionally fewer steps, save its");
+    println!("                      segmentation right away, and continue from its pheromones");
+    println!("                      at full resolution, fine details are missed by the coarse");
+    println!("                      pass, so the preview is only a rough approximation");
+    println!("      --connectivity 4|8|legacy");
+    println!("                      neighbourhood of ant movement, objectives and regions, legacy");
+    println!("                      uses 8 for movement and objectives, but fills regions with 4");
+    println!("                      so that they cannot leak through contours (default: legacy)");
+    println!("      --candidates all|top:K|random:K");
+    println!("                      neighbours each ant step is chosen from, either all of them,");
+    println!("                      the K most attractive ones, or K random ones (default: all)");
+    println!("      --quantize LEVELS");
+    println!("                      reduce the image to a palette of at most LEVELS colors by");
+    println!("                      median cut before the colony runs, so that imperceptible");
+    println!("                      differences do not produce edges, the outputs are still");
+    println!("                      colored from the original image");
+    println!("      --plot          plot the objectives of all solutions into pareto_plot.png,");
+    println!("                      with edge value and connectivity as axes and deviation");
+    println!("                      as color, each point labeled with the index of its solution");
+    println!("      --heatmap       export the final pheromones of each attempt as a heatmap");
+    println!("                      overlaid onto the image");
+    println!("      --trajectories NUM");
+    println!("                      draw the paths of NUM ants walking on the final pheromones");
+    println!("                      of each attempt onto the image");
+    println!("      --raw-names     name solutions by the sums of their objectives, instead of");
+    println!("                      their averages per pixel, the sums are always stored in the");
+    println!("                      metadata of PNG images");
+    println!("      --channel-weights W1,W2,...");
+    println!("                      weight pheromone channels when forming the final contours");
+    println!("      --channel-distance-weights R,G,B");
+    println!("                      weight color channels when comparing colors, for images");
+    println!("                      where some channels carry more signal than others");
+    println!("      --normalize-sum normalize pheromones to sum up to 1 instead of their max");
+    println!("      --objective-distances EDGE,DEVIATION");
+    println!("                      color distances used to evaluate the edge value and overall");
+    println!("                      deviation, one of euclidean (default), euclidean_squared,");
+    println!("                      manhattan, cosine or cosine_unnormed, the connectivity");
+    println!("                      measure does not depend on colors");
+    println!("      --edge-kernel laplace|straight|custom:K1,K2,...,K9");
+    println!("                      3x3 kernel used to find contours, in row-major order");
+    println!("      --format png|jpg|webp");
+    println!("                      encode segmented images in the given format, if supported by");
+    println!("                      this build, contours of type-1 are always stored as PNG");
+    println!("      --quality N     quality of lossy output formats, trading file size against");
+    println!("                      fidelity, from 1 to 100 for jpg (default: 75)");
+    println!("      --ants NUM      run NUM ants between global updates (default: 40)");
+    println!("      --no-return     let ants stop at their target instead of walking back to");
+    println!("                      where they spawned, which reinforces their paths less");
+    println!("      --return        let ants walk back to where they spawned (default)");
+    println!("      --refine-passes N");
+    println!("                      reassign pixels near segment boundaries to the neighbouring");
+    println!("                      segment of closest mean color, N times (default: 0)");
+    println!("      --append        keep previous results in the results-directory, continuing");
+    println!("                      the numbering of attempts, instead of clearing them, the");
+    println!("                      best solutions and the pareto plot only cover the new run");
+    println!("      --no-clobber    refuse to run if the results-directory holds previous results");
+    println!("      --roi X,Y,W,H   only segment the given rectangle of the image, outputs keep");
+    println!("                      the full image, seed points use its coordinates as well");
+    println!("      --generate checkerboard|gradient|blobs WxH");
+    println!("                      run on a generated image of the given size instead of a file,");
+    println!("                      the image is saved as source.png in the results-directory");
+}
+
+/// Output format of the given extension, if it can be encoded in this build.
+fn parse_format(text: &str) -> Option<ImageFormat> {
+    let format = ImageFormat::from_extension(text.trim())?;
+    // Some formats are only recognized, but their encoders are not part of the build.
+    let test = RgbImage::new(1, 1).write_to(&mut io::Cursor::new(vec![]), format);
+    return test.ok().map(|_| format);
+}
+
+/// Qualities supported by the encoder of the format, none if it is lossless in this build.
+/// Without the `webp-encoder` feature of `image`, WebP is encoded losslessly.
+fn quality_range(format: ImageFormat) -> Option<RangeInclusive<u8>> {
+    return match format {
+        ImageFormat::Jpeg => Some(1..=100),
+        _ => None,
+    };
+}
+
+/// Highest attempt number of files named like "3-step12.png" or "3.png" in the directories.
+fn last_attempt(directories: &[&path::Path]) -> usize {
+    return directories
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            return name.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok();
+        })
+        .max()
+        .unwrap_or(0);
+}
+
+/// Attempts at writing an output file before giving up on it.
+const SAVE_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled with each further retry.
+const SAVE_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Writes an output file, retrying after a short delay if it fails,
+/// as writes to networked or slow file systems may fail transiently.
+/// Files that cannot be written are recorded instead of aborting the run.
+fn save_with_retry<E: fmt::Display>(
+    path: &path::Path, failed: &Mutex<Vec<path::PathBuf>>,
+    save: impl Fn(&path::Path) -> Result<(), E>,
+) {
+    let mut backoff = SAVE_BACKOFF;
+    for attempt in 1..=SAVE_ATTEMPTS {
+        match save(path) {
+            Ok(()) => return,
+            Err(problem) if attempt < SAVE_ATTEMPTS => {
+                warn!("Could not save '{}' ({}), retrying.", path.display(), problem);
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(problem) => {
+                error!("Could not save '{}' ({}), giving up.", path.display(), problem);
+                failed.lock().unwrap().push(path.to_path_buf());
+            }
+        }
+    }
+}
+
+/// Detailed images that may wait to be saved before the colony has to wait for the writer.
+const DETAILED_QUEUE_LENGTH: usize = 16;
+
+/// Saves images on a background thread, so that encoding and writing them does not stall
+/// the colony. The queue is bounded, so that images are not piling up in memory.
+struct BackgroundWriter {
+    sender: mpsc::SyncSender<(path::PathBuf, DynamicImage)>,
+    thread: thread::JoinHandle<Vec<path::PathBuf>>,
+}
+
+impl BackgroundWriter {
+    fn spawn(queue_length: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<(path::PathBuf, DynamicImage)>(queue_length);
+        let thread = thread::spawn(move || {
+            let failed = Mutex::new(vec![]);
+            for (path, img) in receiver {
+                save_with_retry(&path, &failed, |path| img.save(path));
+            }
+            return failed.into_inner().unwrap();
+        });
+        return Self { sender, thread };
+    }
+
+    /// Queues the image, waiting if the queue is full.
+    fn save(&self, path: path::PathBuf, img: DynamicImage) {
+        self.sender.send((path, img)).unwrap();
+    }
+
+    /// Waits until all queued images are saved, returning those that could not be saved.
+    fn finish(self) -> Vec<path::PathBuf> {
+        drop(self.sender);
+        return self.thread.join().unwrap();
+    }
+}
+
+/// Saves an image of a solution, embedding the metadata if it is stored as PNG.
+/// The quality only applies to lossy formats, see `quality_range`.
+fn save_solution_image(
+    img: &RgbImage, path: &path::Path, metadata: &[(&str, String)], quality: Option<u8>,
+    failed: &Mutex<Vec<path::PathBuf>>,
+) {
+    let format = ImageFormat::from_path(path).ok();
+    if format == Some(ImageFormat::Png) {
+        save_with_retry(path, failed, |path| {
+            image_arithmetic::save_png_with_text(img, path, metadata)
+        });
+    } else if let (Some(ImageFormat::Jpeg), Some(quality)) = (format, quality) {
+        save_with_retry(path, failed, |path| -> image::ImageResult<()> {
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            return JpegEncoder::new_with_quality(&mut file, quality).encode_image(img);
+        });
+    } else {
+        save_with_retry(path, failed, |path| img.save(path));
+    }
+}
+
+fn parse_rectangle(text: &str) -> Option<(u32, u32, u32, u32)> {
+    let values: Vec<u32> = text.split(',').map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+    if values.len() != 4 {
+        return None;
+    }
+    return Some((values[0], values[1], values[2], values[3]));
+}
+
+fn parse_candidate_list(text: &str) -> Option<image_ants::CandidateList> {
+    if text == "all" {
+        return Some(image_ants::CandidateList::All);
+    }
+    let (kind, amount) = text.split_once(':')?;
+    let amount = amount.parse::<usize>().ok().filter(|&amount| amount > 0)?;
+    return match kind {
+        "top" => Some(image_ants::CandidateList::Strongest(amount)),
+        "random" => Some(image_ants::CandidateList::Random(amount)),
+        _ => None,
+    };
+}
+
+fn parse_objectives(text: &str) -> Option<segment_generation::ObjectiveSelection> {
+    let mut objectives = segment_generation::ObjectiveSelection {
+        edge_value: false,
+        connectivity_measure: false,
+        overall_deviation: false,
+    };
+    for name in text.split(',') {
+        match name.trim() {
+            "edge" => objectives.edge_value = true,
+            "connectivity" => objectives.connectivity_measure = true,
+            "deviation" => objectives.overall_deviation = true,
+            _ => return None,
+        }
+    }
+    // Ants need at least one channel to follow.
+    if !objectives.edge_value && !objectives.connectivity_measure {
+        return None;
+    }
+    return Some(objectives);
+}
+
+fn parse_target_segments(text: &str) -> Option<segment_generation::TargetSegments> {
+    let (count, tolerance) = text.split_once(',').unwrap_or((text, "0"));
+    let count = count.trim().parse().ok().filter(|&count| count > 0)?;
+    let tolerance = tolerance.trim().parse().ok()?;
+    return Some(segment_generation::TargetSegments { count, tolerance });
+}
+
+/// Attempts without improvement before a restart, and the fraction of pheromones kept.
+fn parse_restart_patience(text: &str) -> Option<(usize, f32)> {
+    let (patience, keep) = text.split_once(',').unwrap_or((text, "0"));
+    let patience = patience.trim().parse().ok().filter(|&patience| patience > 0)?;
+    let keep = keep.trim().parse().ok().filter(|keep| (0.0..=1.0).contains(keep))?;
+    return Some((patience, keep));
+}
+
+fn parse_threshold_sweep(text: &str) -> Option<segment_generation::ThresholdSweep> {
+    let values: Vec<f32> = text.split(',').map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+    if values.len() != 3 {
+        return None;
+    }
+    let (start, end, step) = (values[0], values[1], values[2]);
+    if !(0.0..=1.0).contains(&start)
+        || !(start..=1.0).contains(&end)
+        || step.is_nan()
+        || step <= 0.0
+    {
+        return None;
+    }
+    return Some(segment_generation::ThresholdSweep { start, end, step });
+}
+
+fn parse_size(text: &str) -> Option<(u32, u32)> {
+    let (width, height) = text.split_once('x')?;
+    let size = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+    if size.0 < 3 || size.1 < 3 {
+        return None;
+    }
+    return Some(size);
+}
+
+fn parse_points(text: &str) -> Option<Vec<Point>> {
+    let mut points = vec![];
+    for pair in text.split(';').filter(|s| !s.trim().is_empty()) {
+        let (x, y) = pair.split_once(',')?;
+        points.push(Point { x: x.trim().parse().ok()?, y: y.trim().parse().ok()? });
+    }
+    return Some(points);
+}
+
+fn parse_color(text: &str) -> Option<Rgb<u8>> {
+    let channels: Vec<u8> =
+        text.split(',').map(|c| c.trim().parse().ok()).collect::<Option<_>>()?;
+    if channels.len() != 3 {
+        return None;
+    }
+    return Some(Rgb([channels[0], channels[1], channels[2]]));
+}
+
+/// Explains which seeds `parse_seed` accepts.
+fn seed_problem() -> String {
+    let bytes = std::mem::size_of::<<SmallRng as SeedableRng>::Seed>();
+    return format!("Seed must be a positive integer or a hexadecimal string of {} bytes!", bytes);
+}
+
+/// Seeds the RNG from an integer, or a hexadecimal string covering its full seed.
+fn parse_seed(text: &str) -> Option<SmallRng> {
+    let text = text.trim();
+    if let Ok(seed) = text.parse::<u64>() {
+        return Some(SmallRng::seed_from_u64(seed));
+    }
+    let mut seed = <SmallRng as SeedableRng>::Seed::default();
+    let bytes = seed.as_mut();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    if !text.is_ascii() || text.len() != 2 * bytes.len() {
+        return None;
+    }
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+    return Some(SmallRng::from_seed(seed));
+}
+
+fn parse_weights(text: &str) -> Option<Vec<f32>> {
+    let weights: Vec<f32> =
+        text.split(',').map(|w| w.trim().parse().ok()).collect::<Option<_>>()?;
+    if !weights.iter().all(|&w| w.is_finite() && w >= 0.0) {
+        return None;
+    }
+    return Some(weights);
+}
+
+fn parse_distance(text: &str) -> Option<&'static ColorSpaceDistance> {
+    return match text.trim().to_lowercase().as_str() {
+        "euclidean" => Some(&color_distances::euclidean),
+        "euclidean_squared" => Some(&color_distances::euclidean_squared),
+        "manhattan" => Some(&color_distances::manhattan),
+        "cosine" => Some(&color_distances::cosine),
+        "cosine_unnormed" => Some(&color_distances::cosine_unnormed),
+        _ => None,
+    };
+}
+
+/// Distances for edge value and overall deviation, in this order.
+fn parse_objective_distances(text: &str) -> Option<segment_generation::ObjectiveDistances> {
+    let distances: Vec<_> = text.split(',').map(parse_distance).collect::<Option<_>>()?;
+    if distances.len() != 2 {
+        return None;
+    }
+    return Some(segment_generation::ObjectiveDistances {
+        edge_value: distances[0],
+        overall_deviation: distances[1],
+    });
+}
+
+fn parse_kernel(text: &str) -> Option<[f32; 9]> {
+    return match text.trim() {
+        "laplace" => Some(*image_arithmetic::LAPLACE_KERNEL),
+        "straight" => Some(*image_arithmetic::STRAIGHT_LAPLACE_KERNEL),
+        custom => {
+            let values: Vec<f32> = custom
+                .strip_prefix("custom:")?
+                .split(',')
+                .map(|v| v.trim().parse().ok())
+                .collect::<Option<_>>()?;
+            values.try_into().ok()
+        }
+    };
+}
+
+/// Attempts in a row that did not add to the pareto front, see `restart_patience`.
+#[derive(Debug, Default)]
+struct Stagnation {
+    unimproved_attempts: usize,
+}
+
+impl Stagnation {
+    fn record(&mut self, improved: bool) {
+        if improved {
+            self.unimproved_attempts = 0;
+        } else {
+            self.unimproved_attempts += 1;
+        }
+    }
+
+    /// Returns the amount of unimproved attempts once the patience ran out,
+    /// and starts counting anew for the restarted colony.
+    fn restart(&mut self, patience: usize) -> Option<usize> {
+        if self.unimproved_attempts < patience {
+            return None;
+        }
+        return Some(std::mem::take(&mut self.unimproved_attempts));
+    }
+}
+
+/// Adds the solution to the pareto front, if it is not dominated.
+/// Returns whether it was accepted.
+/// The solution is logged by the name of its outputs, see `ParetoPheromones::file_stem`.
+fn push_solution(
+    front: &mut ParetoFront<pareto_pheromones::ParetoPheromones>,
+    solution: pareto_pheromones::ParetoPheromones, normalized: bool,
+) -> bool {
+    let stem = solution.file_stem(normalized);
+    if front.push(solution) {
+        debug!("Accepted solution {} into the pareto front of {}.", stem, front.len());
+        return true;
+    }
+    return false;
+}
+
+/// The text as a JSON string literal.
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+/// The number as a JSON value, which has no representation for infinite values or NaN.
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        return value.to_string();
+    }
+    return String::from("null");
+}
+
+/// The named metrics as a JSON object, spaces in their names replaced by underscores.
+/// Metrics that are not finite are written as null.
+fn metrics_json(metrics: &[(&str, f64)]) -> String {
+    let fields: Vec<String> = metrics
+        .iter()
+        .map(|(name, value)| format!("  \"{}\": {}", name.replace(' ', "_"), json_number(*value)))
+        .collect();
+    return format!("{{\n{}\n}}\n", fields.join(",\n"));
+}
+
+/// Record of a run as newline-delimited JSON, one object per event.
+/// Each event is written right away, so a crashed run still leaves a readable log,
+/// and events of later runs are appended, so a log can span resumed runs.
+struct EventLog {
+    file: Mutex<fs::File>,
+}
+
+impl EventLog {
+    fn open(path: &path::Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        return Ok(Self { file: Mutex::new(file) });
+    }
+
+    /// Writes the event with the given fields, whose values need to be valid JSON already.
+    /// Problems writing the log are reported, but do not abort the run.
+    fn record(&self, event: &str, fields: &[(&str, String)]) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = format!(
+            "{{\"event\":{},\"time\":{}",
+            json_string(event),
+            json_number(time.as_secs_f64())
+        );
+        for (name, value) in fields {
+            line.push_str(&format!(",{}:{}", json_string(name), value));
+        }
+        line.push_str("}\n");
+        let mut file = self.file.lock().unwrap();
+        if let Err(problem) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            error!("Could not write to the event log ({}).", problem);
+        }
+    }
+
+    /// Fields describing the solution, see `ParetoPheromones::metadata`.
+    fn solution_fields(
+        solution: &pareto_pheromones::ParetoPheromones,
+    ) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("segments", solution.segment_count.to_string()),
+            ("threshold", json_number(solution.threshold as f64)),
+            ("edge_value", json_number(solution.edge_value)),
+            ("connectivity_measure", json_number(solution.connectivity_measure)),
+            ("overall_deviation", json_number(solution.overall_deviation)),
+        ];
+        if let Some(entropy) = solution.overall_entropy {
+            fields.push(("overall_entropy", json_number(entropy)));
+        }
+        fields.push(("fingerprint", json_string(&format!("{:016x}", solution.fingerprint))));
+        return fields;
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program_name: Option<&str> = Some(args[0].as_str());
+    // Known upfront, so that it also applies to problems with preceding options.
+    let quiet = args.iter().any(|arg| arg == "-q" || arg == "--quiet");
+    // Show warnings by default, more can be enabled with `RUST_LOG`.
+    let log_level = if quiet { "error" } else { "warn" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+    // Stdout is reserved for results, so informational messages go to stderr.
+    let inform = |message: &str| {
+        if !quiet {
+            eprintln!("{}", message);
+        }
+    };
 
     let mut detailed = false;
     let mut evaluate_every_step = false;
     let mut rng = SmallRng::from_entropy();
     let mut soft_timeout = None;
+    let mut max_attempts = None;
+    let mut colonies = 1;
+    let mut patience = None;
     let mut parallelity = None;
     let mut multi_objective = true;
+    let mut options = segment_generation::SegmentationOptions::default();
+    let mut seed_points = vec![];
+    let mut export_path = None;
+    let mut overlay_color = segment_generation::DEFAULT_OVERLAY_COLOR;
+    let mut overlay_opacity = segment_generation::DEFAULT_OVERLAY_OPACITY;
+    let mut labels_path = None;
+    let mut contour_path = None;
+    let mut invert_contour = false;
+    let mut salient_spawn = false;
+    let mut transparent = false;
+    let mut legend = false;
+    let mut ground_truth_path = None;
+    let mut tolerance = 2;
+    let mut revisit_decay = 1.0;
+    let mut heuristic = image_ants::AntHeuristicParams::default();
+    let mut max_target_distance = None;
+    let mut preview = None;
+    let mut compact = false;
+    let mut warm_restart = None;
+    let mut restart_patience = None;
+    let mut deterministic = false;
+    let mut seeded = false;
+    let mut region_blend = 1.0;
+    let mut region_coloring = segment_generation::SegmentColoring::Mean;
+    let mut stall_window = None;
+    let mut ant_time_budget = None;
+    let mut candidate_list = image_ants::CandidateList::All;
+    let mut heatmap = false;
+    let mut plot = false;
+    let mut quantize_colors = None;
+    let mut trajectories = None;
+    let mut raw_names = false;
+    let mut channel_weights = None;
+    let mut output_format = ImageFormat::Png;
+    let mut quality = None;
+    let mut generate = None;
+    let mut compare = None;
+    let mut compare_json_path = None;
+    let mut stats_path = None;
+    let mut log_json_path = None;
+    let mut append = false;
+    let mut roi = None;
+    let mut no_clobber = false;
 
     let usage_and_exit = |problem: Option<&str>| {
-        let mut code = 0;
         if problem != None {
             eprintln!("{}", problem.unwrap());
-            code = 1;
+            inform(&format!("Use '{} --help' to list all options.", args[0]));
+            process::exit(1);
         }
         usage(program_name);
-        process::exit(code);
+        process::exit(0);
     };
 
     let mut parameters = Vec::new();
@@ -74,6 +768,7 @@ fn main() {
             match arg.as_str() {
                 s if !s.starts_with("-") => parameters.push(arg.clone()),
                 "-h" | "--help" => usage_and_exit(None),
+                "-q" | "--quiet" => (),
                 "-d" | "--detailed" => detailed = true,
                 "-e" | "--eval-steps" | "--evaluate-steps" => evaluate_every_step = true,
                 "-o" | "--objective" => match get_parameter().to_lowercase().as_str() {
@@ -81,9 +776,29 @@ fn main() {
                     "s" | "single" => multi_objective = false,
                     _ => usage_and_exit(Some("Unknown objective!")),
                 },
-                "-s" | "--seed" => match get_parameter().parse::<u64>() {
-                    Ok(seed) => rng = SmallRng::seed_from_u64(seed),
-                    _ => usage_and_exit(Some("Seed must be a positive integer!")),
+                "--objectives" => match parse_objectives(get_parameter()) {
+                    Some(objectives) => options.objectives = objectives,
+                    _ => usage_and_exit(Some(
+                        "Objectives must be a list of edge, connectivity and deviation, \
+                         with edge or connectivity among them!",
+                    )),
+                },
+                "-s" | "--seed" => match parse_seed(get_parameter()) {
+                    Some(seeded_rng) => {
+                        rng = seeded_rng;
+                        seeded = true;
+                    }
+                    _ => usage_and_exit(Some(&seed_problem())),
+                },
+                "--seed-file" => match fs::read_to_string(get_parameter()) {
+                    Ok(text) => match parse_seed(&text) {
+                        Some(seeded_rng) => {
+                            rng = seeded_rng;
+                            seeded = true;
+                        }
+                        _ => usage_and_exit(Some(&seed_problem())),
+                    },
+                    Err(_) => usage_and_exit(Some("Seed file cannot be read!")),
                 },
                 "-t" | "--timeout" => match get_parameter().parse::<u64>() {
                     Ok(secs) => soft_timeout = Some(Duration::from_secs(secs)),
@@ -96,98 +811,1363 @@ fn main() {
                     Ok(num) => parallelity = Some(num),
                     _ => usage_and_exit(Some("Parallelity must a positive integer!")),
                 },
+                "--colonies" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Number of colonies cannot be 0!")),
+                    Ok(num) => colonies = num,
+                    _ => usage_and_exit(Some("Number of colonies must be a positive integer!")),
+                },
+                "-a" | "--attempts" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Attempts cannot be 0!")),
+                    Ok(num) => max_attempts = Some(num),
+                    _ => usage_and_exit(Some("Attempts must be a positive integer!")),
+                },
+                "--converge" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Convergence patience cannot be 0!")),
+                    Ok(num) => patience = Some(num),
+                    _ => usage_and_exit(Some("Convergence patience must be a positive integer!")),
+                },
+                "--seed-points" => match parse_points(get_parameter()) {
+                    Some(points) if !points.is_empty() => seed_points = points,
+                    _ => usage_and_exit(Some("Seed points must be given as X1,Y1;X2,Y2;...!")),
+                },
+                "--export-pheromones" => export_path = Some(path::PathBuf::from(get_parameter())),
+                "--connectivity-weighting" => match get_parameter().to_lowercase().as_str() {
+                    "i" | "index" => {
+                        options.connectivity_weighting = segments::ConnectivityWeighting::Index
+                    }
+                    "d" | "distance" => {
+                        options.connectivity_weighting = segments::ConnectivityWeighting::Distance
+                    }
+                    _ => usage_and_exit(Some("Unknown connectivity weighting!")),
+                },
+                "--edge-weighting" => match get_parameter().to_lowercase().as_str() {
+                    "i" | "isotropic" => {
+                        options.edge_weighting = segments::EdgeWeighting::Isotropic
+                    }
+                    "g" | "gradient" => options.edge_weighting = segments::EdgeWeighting::Gradient,
+                    _ => usage_and_exit(Some("Unknown edge weighting!")),
+                },
+                "--fast" => options.fast = true,
+                "--incremental" => options.incremental = true,
+                "--wrap" => options.wrap = true,
+                "--open-border" => options.open_border = true,
+                "--overlay-color" => match parse_color(get_parameter()) {
+                    Some(color) => overlay_color = color,
+                    _ => usage_and_exit(Some("Overlay color must be given as R,G,B!")),
+                },
+                "--overlay-opacity" => match get_parameter().parse::<u8>() {
+                    Ok(opacity) => overlay_opacity = opacity,
+                    _ => usage_and_exit(Some("Overlay opacity must be an integer from 0 to 255!")),
+                },
+                "--evaluate" => labels_path = Some(get_parameter().clone()),
+                "--evaluate-contour" => contour_path = Some(get_parameter().clone()),
+                "--invert-contour" => invert_contour = true,
+                "--salient-spawn" => salient_spawn = true,
+                "--transparent" => transparent = true,
+                "--legend" => legend = true,
+                "--clamp-fraction" => match get_parameter().parse::<f32>() {
+                    Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => {
+                        options.clamp_fraction = fraction
+                    }
+                    _ => usage_and_exit(Some("Clamp fraction must be a number in (0, 1]!")),
+                },
+                "--update-threshold" => match get_parameter().parse::<f32>() {
+                    Ok(threshold) if (0.0..=1.0).contains(&threshold) => {
+                        options.update_threshold = threshold
+                    }
+                    _ => usage_and_exit(Some("Update threshold must be a number in [0, 1]!")),
+                },
+                "--threshold-sweep" => match parse_threshold_sweep(get_parameter()) {
+                    Some(sweep) => options.threshold_sweep = Some(sweep),
+                    _ => usage_and_exit(Some(
+                        "Threshold sweep must be given as START,END,STEP with \
+                         0 <= START <= END <= 1 and STEP > 0!",
+                    )),
+                },
+                "--target-segments" => match parse_target_segments(get_parameter()) {
+                    Some(target) => options.target_segments = Some(target),
+                    _ => usage_and_exit(Some(
+                        "Target segments must be given as K or K,TOLERANCE \
+                         with positive K and non-negative TOLERANCE!",
+                    )),
+                },
+                "--entropy-objective" => options.entropy_objective = true,
+                "--gt-edges" => ground_truth_path = Some(get_parameter().clone()),
+                "--tolerance" => match get_parameter().parse::<u32>() {
+                    Ok(distance) => tolerance = distance,
+                    _ => usage_and_exit(Some("Tolerance must be a non-negative integer!")),
+                },
+                "--revisit-decay" => match get_parameter().parse::<f32>() {
+                    Ok(rate) if rate > 0.0 && rate <= 1.0 => revisit_decay = rate,
+                    _ => usage_and_exit(Some("Revisit decay must be a number in (0, 1]!")),
+                },
+                "--alpha" => match get_parameter().parse::<f32>() {
+                    Ok(alpha) if alpha >= 0.0 && alpha.is_finite() => heuristic.alpha = alpha,
+                    _ => usage_and_exit(Some("Alpha must be a non-negative number!")),
+                },
+                "--beta" => match get_parameter().parse::<f32>() {
+                    Ok(beta) if beta >= 0.0 && beta.is_finite() => heuristic.beta = beta,
+                    _ => usage_and_exit(Some("Beta must be a non-negative number!")),
+                },
+                "--revisit-penalty" => match get_parameter().parse::<f32>() {
+                    Ok(penalty) if (0.0..=1.0).contains(&penalty) => {
+                        heuristic.revisit_penalty = penalty
+                    }
+                    _ => usage_and_exit(Some("Revisit penalty must be a number in [0, 1]!")),
+                },
+                "--color-divisor" => match get_parameter().parse::<f32>() {
+                    Ok(divisor) if divisor > 0.0 && divisor.is_finite() => {
+                        heuristic.color_divisor = divisor
+                    }
+                    _ => usage_and_exit(Some("Color divisor must be a positive number!")),
+                },
+                "--connectivity" => match get_parameter().as_str() {
+                    "4" => {
+                        options.connectivity = segment_generation::PipelineConnectivity::Uniform(
+                            image_arithmetic::Connectivity::Four,
+                        )
+                    }
+                    "8" => {
+                        options.connectivity = segment_generation::PipelineConnectivity::Uniform(
+                            image_arithmetic::Connectivity::Eight,
+                        )
+                    }
+                    "legacy" => {
+                        options.connectivity = segment_generation::PipelineConnectivity::Legacy
+                    }
+                    _ => usage_and_exit(Some("Connectivity must be 4, 8 or legacy!")),
+                },
+                "--preview" => match get_parameter().parse::<u32>() {
+                    Ok(scale) if scale >= 2 => preview = Some(scale),
+                    _ => usage_and_exit(Some("Preview scale must be an integer of at least 2!")),
+                },
+                "--max-target-distance" => match get_parameter().parse::<u32>() {
+                    Ok(0) => usage_and_exit(Some("Maximum target distance cannot be 0!")),
+                    Ok(distance) => max_target_distance = Some(distance),
+                    _ => {
+                        usage_and_exit(Some("Maximum target distance must be a positive integer!"))
+                    }
+                },
+                "--compact" => compact = true,
+                "--restart-patience" => match parse_restart_patience(get_parameter()) {
+                    Some(patience) => restart_patience = Some(patience),
+                    _ => usage_and_exit(Some(
+                        "Restart patience must be given as K or K,KEEP \
+                         with positive K and KEEP in [0, 1]!",
+                    )),
+                },
+                "--warm-restart" => match get_parameter().parse::<f32>() {
+                    Ok(noise) if (0.0..=1.0).contains(&noise) => warm_restart = Some(noise),
+                    _ => usage_and_exit(Some("Restart noise must be a number in [0, 1]!")),
+                },
+                "--deterministic" => deterministic = true,
+                "--edge-deposit" => match get_parameter().parse::<f32>() {
+                    Ok(amount) if amount >= 0.0 && amount.is_finite() => {
+                        options.edge_deposit = amount
+                    }
+                    _ => usage_and_exit(Some("Edge deposit must be a non-negative number!")),
+                },
+                "--connectivity-deposit" => match get_parameter().parse::<f32>() {
+                    Ok(amount) if amount >= 0.0 && amount.is_finite() => {
+                        options.connectivity_deposit = amount
+                    }
+                    _ => {
+                        usage_and_exit(Some("Connectivity deposit must be a non-negative number!"))
+                    }
+                },
+                "--initial-pheromone" => match get_parameter().parse::<f32>() {
+                    Ok(amount) if amount >= 0.0 && amount.is_finite() => {
+                        options.initial_pheromone = amount
+                    }
+                    _ => usage_and_exit(Some("Initial pheromone must be a non-negative number!")),
+                },
+                "--region-color" => match get_parameter().to_lowercase().as_str() {
+                    "mean" => region_coloring = segment_generation::SegmentColoring::Mean,
+                    "mode" => region_coloring = segment_generation::SegmentColoring::Mode,
+                    "stable" => region_coloring = segment_generation::SegmentColoring::Stable,
+                    "index" => region_coloring = segment_generation::SegmentColoring::Index,
+                    _ => usage_and_exit(Some("Region color must be mean, mode, stable or index!")),
+                },
+                "--segment-order" => match get_parameter().to_lowercase().as_str() {
+                    "raster" => options.segment_order = segments::SegmentOrder::Raster,
+                    "size" => options.segment_order = segments::SegmentOrder::Size,
+                    _ => usage_and_exit(Some("Segment order must be raster or size!")),
+                },
+                "--region-blend" => match get_parameter().parse::<f32>() {
+                    Ok(factor) if (0.0..=1.0).contains(&factor) => region_blend = factor,
+                    _ => usage_and_exit(Some("Region blend must be a number in [0, 1]!")),
+                },
+                "--stall-window" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Stall window cannot be 0!")),
+                    Ok(steps) => stall_window = Some(steps),
+                    _ => usage_and_exit(Some("Stall window must be a positive integer!")),
+                },
+                "--ant-time-budget" => match get_parameter().parse::<u64>() {
+                    Ok(0) => usage_and_exit(Some("Ant time budget cannot be 0!")),
+                    Ok(millis) => ant_time_budget = Some(Duration::from_millis(millis)),
+                    _ => usage_and_exit(Some("Ant time budget must be a positive integer!")),
+                },
+                "--candidates" => match parse_candidate_list(get_parameter()) {
+                    Some(list) => candidate_list = list,
+                    _ => usage_and_exit(Some(
+                        "Candidates must be all, top:K or random:K with a positive integer K!",
+                    )),
+                },
+                "--heatmap" => heatmap = true,
+                "--plot" => plot = true,
+                "--quantize" => match get_parameter().parse::<usize>() {
+                    Ok(colors) if colors >= 2 => quantize_colors = Some(colors),
+                    _ => usage_and_exit(Some("Quantization levels must be an integer of at least 2!")),
+                },
+                "--trajectories" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Number of trajectories cannot be 0!")),
+                    Ok(num) => trajectories = Some(num),
+                    _ => usage_and_exit(Some("Number of trajectories must be a positive integer!")),
+                },
+                "--raw-names" => raw_names = true,
+                "--refine-passes" => match get_parameter().parse::<usize>() {
+                    Ok(passes) => options.refine_passes = passes,
+                    _ => usage_and_exit(Some("Refinement passes must be a non-negative integer!")),
+                },
+                "--ants" => match get_parameter().parse::<usize>() {
+                    Ok(0) => usage_and_exit(Some("Number of ants cannot be 0!")),
+                    Ok(num) => options.ants_per_global_update = Some(num),
+                    _ => usage_and_exit(Some("Number of ants must be a positive integer!")),
+                },
+                "--no-return" => options.ants_return = false,
+                "--return" => options.ants_return = true,
+                "--append" => append = true,
+                "--roi" => match parse_rectangle(get_parameter()) {
+                    Some(rectangle) => roi = Some(rectangle),
+                    _ => usage_and_exit(Some("Region of interest must be given as X,Y,W,H!")),
+                },
+                "--no-clobber" => no_clobber = true,
+                "--compare" => {
+                    let a = get_parameter().clone();
+                    compare = Some((a, get_parameter().clone()));
+                }
+                "--compare-json" => compare_json_path = Some(get_parameter().clone()),
+                "--stats-csv" => stats_path = Some(get_parameter().clone()),
+                "--log-json" => log_json_path = Some(get_parameter().clone()),
+                "--generate" => {
+                    let kind = match get_parameter().as_str() {
+                        "checkerboard" => Some(synthetic::SyntheticKind::Checkerboard),
+                        "gradient" => Some(synthetic::SyntheticKind::Gradient),
+                        "blobs" => Some(synthetic::SyntheticKind::Blobs),
+                        _ => None,
+                    };
+                    match (kind, parse_size(get_parameter())) {
+                        (None, _) => usage_and_exit(Some("Unknown kind of generated image!")),
+                        (Some(kind), Some(size)) => generate = Some((kind, size)),
+                        _ => usage_and_exit(Some("Image size must be given as WxH, at least 3x3!")),
+                    }
+                }
+                "--format" => match parse_format(get_parameter()) {
+                    Some(format) => output_format = format,
+                    _ => usage_and_exit(Some("Output format is unknown or not supported!")),
+                },
+                "--quality" => match get_parameter().parse::<u8>() {
+                    Ok(value) => quality = Some(value),
+                    _ => usage_and_exit(Some("Quality must be an integer from 1 to 100!")),
+                },
+                "--channel-weights" => match parse_weights(get_parameter()) {
+                    Some(weights) => channel_weights = Some(weights),
+                    _ => usage_and_exit(Some("Channel weights must be non-negative numbers!")),
+                },
+                "--channel-distance-weights" => match parse_weights(get_parameter()).as_deref() {
+                    Some(&[r, g, b]) => {
+                        options.channel_distance_weights = Some([r as f64, g as f64, b as f64])
+                    }
+                    _ => usage_and_exit(Some(
+                        "Channel distance weights must be 3 non-negative numbers!",
+                    )),
+                },
+                "--normalize-sum" => options.normalize_sum = true,
+                "--objective-distances" => match parse_objective_distances(get_parameter()) {
+                    Some(distances) => options.objective_distances = distances,
+                    _ => usage_and_exit(Some(
+                        "Objective distances must be 2 of euclidean, euclidean_squared, manhattan, \
+                        cosine or cosine_unnormed!",
+                    )),
+                },
+                "--edge-kernel" => match parse_kernel(get_parameter()) {
+                    Some(kernel) => options.edge_kernel = kernel,
+                    _ => usage_and_exit(Some(
+                        "Edge kernel must be laplace, straight or custom: with 9 numbers!",
+                    )),
+                },
                 _ => usage_and_exit(Some(format!("Unknown option '{}'!", arg).as_str())),
             }
         }
         i += 1;
     }
 
-    if parameters.len() < 2 {
+    if let Some(quality) = quality {
+        match quality_range(output_format) {
+            Some(range) if range.contains(&quality) => {}
+            Some(range) => usage_and_exit(Some(&format!(
+                "Quality of {} must be from {} to {}!",
+                output_format.extensions_str()[0],
+                range.start(),
+                range.end()
+            ))),
+            None => usage_and_exit(Some(&format!(
+                "Quality is not supported by {}, which is encoded losslessly!",
+                output_format.extensions_str()[0]
+            ))),
+        }
+    }
+    if deterministic {
+        if parallelity.map_or(false, |p| p > 1) {
+            usage_and_exit(Some("Deterministic mode cannot run in parallel!"));
+        }
+        parallelity = Some(1);
+        if !seeded {
+            rng = SmallRng::seed_from_u64(0);
+        }
+    }
+
+    if let Some((labels_a_path, labels_b_path)) = compare {
+        let read_labels = |path: &str| {
+            let decoded =
+                ImageReader::open(path).map_err(ImageError::from).and_then(|r| r.decode());
+            return match decoded {
+                Ok(labels) => labels.to_rgb8(),
+                Err(problem) => {
+                    eprintln!("Could not read the label image '{}' ({})!", path, problem);
+                    process::exit(1);
+                }
+            };
+        };
+        let labels_a = read_labels(&labels_a_path);
+        let labels_b = read_labels(&labels_b_path);
+        if labels_a.dimensions() != labels_b.dimensions() {
+            usage_and_exit(Some("Label images must have the same dimensions!"));
+        }
+        let segments_a = segments::segments_from_labels(&labels_a);
+        let segments_b = segments::segments_from_labels(&labels_b);
+        let agreement = segments::SegmentationAgreement::new(&segments_a, &segments_b);
+        let metrics = [
+            ("segments a", segments_a.len() as f64),
+            ("segments b", segments_b.len() as f64),
+            ("rand index", agreement.rand_index),
+            ("variation of information", agreement.variation_of_information),
+            ("covering", agreement.covering),
+            ("reverse covering", agreement.reverse_covering),
+        ];
+        for (name, value) in metrics {
+            println!("{}: {}", name, value);
+        }
+        if let Some(json_path) = compare_json_path {
+            fs::write(json_path, metrics_json(&metrics)).unwrap();
+        }
+        return;
+    }
+
+    if let Some(contour_path) = contour_path {
+        if parameters.is_empty() {
+            usage_and_exit(Some("Too few arguments!"));
+        }
+        let rgb_image = ImageReader::open(&parameters[0]).unwrap().decode().unwrap().to_rgb8();
+        let mut contour_image =
+            ImageReader::open(contour_path).unwrap().decode().unwrap().to_rgb8();
+        if contour_image.dimensions() != rgb_image.dimensions() {
+            usage_and_exit(Some("Contour image must have the same dimensions as the image!"));
+        }
+        if invert_contour {
+            imageops::invert(&mut contour_image);
+        }
+        let contour = segment_generation::contour_points(&contour_image, options.contour_border());
+        let edge_value = segment_generation::contour_objective(&rgb_image, &contour, &options);
+        println!("contour pixels: {}", contour.len());
+        println!("edge value: {}", edge_value);
+        return;
+    }
+
+    if let Some(labels_path) = labels_path {
+        if parameters.is_empty() {
+            usage_and_exit(Some("Too few arguments!"));
+        }
+        let rgb_image = ImageReader::open(&parameters[0]).unwrap().decode().unwrap().to_rgb8();
+        let labels = ImageReader::open(labels_path).unwrap().decode().unwrap().to_rgb8();
+        if labels.dimensions() != rgb_image.dimensions() {
+            usage_and_exit(Some("Label image must have the same dimensions as the image!"));
+        }
+        let regions = segments::segments_from_labels(&labels);
+        let (edge_value, connectivity_measure, overall_deviation) =
+            segment_generation::segment_objectives(&rgb_image, &regions, &options);
+        println!("segments: {}", regions.len());
+        println!("edge value: {}", edge_value);
+        println!("connectivity measure: {}", connectivity_measure);
+        println!("overall deviation: {}", overall_deviation);
+        if options.entropy_objective {
+            println!("overall entropy: {}", segments::overall_entropy(&rgb_image, &regions));
+        }
+        return;
+    }
+
+    // Generated images replace the image path.
+    let required_parameters = if generate.is_some() { 1 } else { 2 };
+    if parameters.len() < required_parameters {
         usage_and_exit(Some("Too few arguments!"));
     }
-    let image_path = &parameters[0];
-    let results_path = path::PathBuf::from(&parameters[1]);
+    let mut generated_seed = None;
+    if !seeded && !deterministic {
+        // Still seed explicitly, so that the run can be reproduced.
+        let seed = rand::random::<u64>();
+        rng = SmallRng::seed_from_u64(seed);
+        inform(&format!("Using seed {}.", seed));
+        generated_seed = Some(seed);
+    }
+    // Derived without advancing the generator, so that drawing trajectories keeps the results.
+    let trajectory_seed: u64 = rng.clone().gen();
+
+    if options.objectives != segment_generation::ObjectiveSelection::default() && !multi_objective {
+        usage_and_exit(Some("Objectives can only be selected for multi objective optimization!"));
+    }
+    if options.target_segments.is_some() && options.threshold_sweep.is_some() {
+        usage_and_exit(Some("A threshold can either be swept or searched, not both!"));
+    }
+    if restart_patience.is_some() && warm_restart.is_none() {
+        usage_and_exit(Some(
+            "Restarts need --warm-restart, attempts start from scratch otherwise!",
+        ));
+    }
+    if warm_restart.is_some() && compact {
+        usage_and_exit(Some("Warm restarts need the individual channels, which --compact drops!"));
+    }
+    let results_path = path::PathBuf::from(&parameters[required_parameters - 1]);
+    let detailed_path = results_path.join("detailed");
+    let heatmap_path = results_path.join("heatmap");
+    let trajectories_path = results_path.join("trajectories");
+    let preview_path = results_path.join("preview");
+    let type_1_path = results_path.join("type_1_segments");
+    let type_2_path = results_path.join("type_2_segments");
+    let type_3_path = results_path.join("type_3_segments");
+    let best_path = results_path.join("best");
+    let plot_path = results_path.join("pareto_plot.png");
+    // Number of the last attempt, so that appended runs continue the numbering
+    // even if no output of the previous runs is named after its attempts.
+    let last_attempt_path = results_path.join("last_attempt.txt");
+    let output_paths = [
+        &detailed_path,
+        &heatmap_path,
+        &trajectories_path,
+        &preview_path,
+        &type_1_path,
+        &type_2_path,
+        &type_3_path,
+        &best_path,
+    ];
+
+    // Results of previous runs would otherwise silently mix with the new ones.
+    let occupied = output_paths
+        .iter()
+        .any(|path| fs::read_dir(path).map_or(false, |mut entries| entries.next().is_some()))
+        || plot_path.exists()
+        || last_attempt_path.exists();
+    let mut previous_attempts = 0;
+    if append {
+        let mut numbered_paths = vec![
+            detailed_path.as_path(),
+            heatmap_path.as_path(),
+            trajectories_path.as_path(),
+            preview_path.as_path(),
+        ];
+        numbered_paths.extend(export_path.as_deref());
+        let recorded = fs::read_to_string(&last_attempt_path).ok();
+        previous_attempts = last_attempt(&numbered_paths)
+            .max(recorded.and_then(|text| text.trim().parse().ok()).unwrap_or(0));
+        // Solutions of previous runs are not loaded, so these would mix both runs.
+        if best_path.exists() {
+            fs::remove_dir_all(&best_path).unwrap();
+        }
+        if plot_path.exists() {
+            fs::remove_file(&plot_path).unwrap();
+        }
+    } else if occupied {
+        if no_clobber {
+            eprintln!("Results-directory already holds results, refusing to overwrite them!");
+            process::exit(1);
+        }
+        for path in output_paths.iter().filter(|path| path.exists()) {
+            fs::remove_dir_all(path).unwrap();
+        }
+        if plot_path.exists() {
+            fs::remove_file(&plot_path).unwrap();
+        }
+        if last_attempt_path.exists() {
+            fs::remove_file(&last_attempt_path).unwrap();
+        }
+    }
 
     let mut dirbuilder = fs::DirBuilder::new();
     dirbuilder.recursive(true);
     dirbuilder.create(&results_path).unwrap();
 
-    let detailed_path = results_path.join("detailed");
     if detailed {
         dirbuilder.create(&detailed_path).unwrap();
     }
+    if let Some(export_path) = &export_path {
+        dirbuilder.create(export_path).unwrap();
+    }
+    if heatmap {
+        dirbuilder.create(&heatmap_path).unwrap();
+    }
+    if trajectories.is_some() {
+        dirbuilder.create(&trajectories_path).unwrap();
+    }
+    if preview.is_some() {
+        dirbuilder.create(&preview_path).unwrap();
+    }
+
+    // Statistics of previous attempts are kept when appending.
+    // Colonies running in parallel share the file, their rows are told apart by the attempt.
+    let stats_csv = stats_path.map(|stats_path| {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(stats_path)
+            .unwrap();
+        let empty = file.metadata().unwrap().len() == 0;
+        let mut writer = io::BufWriter::new(file);
+        if empty {
+            writeln!(
+                writer,
+                "attempt,step,channel,{}",
+                image_ants::PheromoneStatistics::CSV_HEADER
+            )
+            .unwrap();
+        }
+        return Mutex::new(writer);
+    });
+
+    let event_log = log_json_path.map(|path| match EventLog::open(path::Path::new(&path)) {
+        Ok(event_log) => event_log,
+        Err(problem) => {
+            eprintln!("Could not open the event log '{}' ({})!", path, problem);
+            process::exit(1);
+        }
+    });
+    if let Some(event_log) = &event_log {
+        event_log.record(
+            "start",
+            &[
+                (
+                    "software",
+                    json_string(&format!("{} {}", PACKAGE_NAME, env!("CARGO_PKG_VERSION"))),
+                ),
+                ("parameters", json_string(&args[1..].join(" "))),
+                ("seed", generated_seed.map_or(String::from("null"), |seed| seed.to_string())),
+            ],
+        );
+    }
+    let log_warning = |message: &str| {
+        if let Some(event_log) = &event_log {
+            event_log.record("warning", &[("message", json_string(message))]);
+        }
+    };
+    let warning = |message: &str| {
+        warn!("{}", message);
+        log_warning(message);
+    };
+
+    // Outputs that could not be written, reported once everything else is done.
+    let failed_saves = Mutex::new(vec![]);
+
+    let input_image = match generate {
+        Some((kind, (width, height))) => {
+            let generated = kind.generate(&mut rng, width, height);
+            save_with_retry(&results_path.join("source.png"), &failed_saves, |path| {
+                generated.save(path)
+            });
+            DynamicImage::from(generated)
+        }
+        None => ImageReader::open(&parameters[0]).unwrap().decode().unwrap(),
+    };
+    let color_type = input_image.color();
+    if color_type.bytes_per_pixel() > color_type.channel_count() {
+        let message = format!(
+            "Image uses more than 8 bits per channel ({:?}), precision is discarded!",
+            color_type
+        );
+        warning(&message);
+    }
+    if color_type.has_alpha() {
+        // Converting to RGB silently drops the alpha channel.
+        let opaque = input_image.to_rgba16().pixels().all(|pixel| pixel.0[3] == u16::MAX);
+        if opaque {
+            inform("Image has an alpha channel, as it is fully opaque it was flattened to RGB.");
+        } else {
+            inform(&format!(
+                "Image has an alpha channel with transparency ({:?}), it was flattened to RGB! \
+                 Transparent pixels are segmented by their stored colors.",
+                color_type
+            ));
+        }
+    }
+    let full_image = input_image.to_rgb8();
+
+    // The colony only works on the region of interest, results are placed back afterwards.
+    let (roi_x, roi_y, roi_width, roi_height) =
+        roi.unwrap_or((0, 0, full_image.width(), full_image.height()));
+    if roi_width < 3
+        || roi_height < 3
+        || roi_x as u64 + roi_width as u64 > full_image.width() as u64
+        || roi_y as u64 + roi_height as u64 > full_image.height() as u64
+    {
+        usage_and_exit(Some("Region of interest must lie within the image, at least 3x3!"));
+    }
+    let crop = |img: &RgbImage| {
+        return imageops::crop_imm(img, roi_x, roi_y, roi_width, roi_height).to_image();
+    };
+    let uncrop = |img: &RgbImage, background: &RgbImage| {
+        let mut canvas = background.clone();
+        imageops::replace(&mut canvas, img, roi_x as i64, roi_y as i64);
+        return canvas;
+    };
+    // Outside of the region of interest there are no pheromones, like where no ant went.
+    let uncrop_pheromones = |pheromones: &[image_ants::PheromoneImage]| {
+        let (width, height) = full_image.dimensions();
+        return pheromones
+            .iter()
+            .map(|pheromone| {
+                let mut canvas = image_ants::PheromoneImage::new(width, height);
+                imageops::replace(&mut canvas, pheromone, roi_x as i64, roi_y as i64);
+                return canvas;
+            })
+            .collect::<Vec<_>>();
+    };
+    let roi_offset = Point { x: roi_x as i64, y: roi_y as i64 };
+    let rgb_image = crop(&full_image);
+    // The colony and the objectives see the quantized image, outputs are colored from the original.
+    let quantized_image =
+        quantize_colors.map(|colors| quantize::quantize_median_cut(&rgb_image, colors));
+    let working_image = quantized_image.as_ref().unwrap_or(&rgb_image);
+    // Outside of the region of interest there are no segments, like on the border.
+    let contour_background = RgbImage::new(full_image.width(), full_image.height());
+
+    let ground_truth = ground_truth_path.map(|ground_truth_path| {
+        let edges = ImageReader::open(ground_truth_path).unwrap().decode().unwrap().to_rgb8();
+        if edges.dimensions() != full_image.dimensions() {
+            usage_and_exit(Some("Ground-truth edges must have the same dimensions as the image!"));
+        }
+        segments::select_points(&crop(&edges), |pixel| pixel.0.iter().any(|&c| c >= 128))
+    });
+
+    for point in seed_points.iter_mut() {
+        point.x -= roi_x as i64;
+        point.y -= roi_y as i64;
+    }
+    let corner_a = Point { x: 0, y: 0 };
+    let corner_b = Point { x: rgb_image.width() as i64 - 1, y: rgb_image.height() as i64 - 1 };
+    if seed_points.iter().any(|p| !p.is_within_rectangle(&corner_a, &corner_b)) {
+        usage_and_exit(Some("Seed points must lie within the image and region of interest!"));
+    }
+
+    let channels = segment_generation::channel_labels(multi_objective).len();
+    if channel_weights.as_ref().map_or(false, |w: &Vec<f32>| w.len() != channels) {
+        usage_and_exit(Some(format!("Expected {} channel weights!", channels).as_str()));
+    }
+
+    if let Err(problem) = heuristic.check() {
+        usage_and_exit(Some(&format!("Heuristic constants are invalid ({})!", problem)));
+    }
+    // Rules for the image scaled down by the given factor, where positions shrink accordingly.
+    let scaled_rules = |img: &RgbImage, scale: u32, parallelity: Option<usize>| {
+        let mut rules =
+            segment_generation::create_rules(img, parallelity, multi_objective, options);
+        let scale_point = |point: &Point| Point {
+            x: (point.x / scale as i64).min(img.width() as i64 - 1),
+            y: (point.y / scale as i64).min(img.height() as i64 - 1),
+        };
+        rules.revisit_decay = revisit_decay;
+        rules.heuristic = heuristic;
+        rules.max_target_distance = max_target_distance.map(|distance| (distance / scale).max(1));
+        rules.deterministic = deterministic;
+        rules.stall_window = stall_window;
+        rules.ant_time_budget = ant_time_budget;
+        rules.candidate_list = candidate_list;
+        if !seed_points.is_empty() {
+            let points = seed_points.iter().map(scale_point).collect();
+            rules.spawn_strategy = image_ants::SpawnStrategy::FromSet(points);
+        } else if salient_spawn {
+            // Images without any gradient cannot be weighted, spawn uniformly then.
+            let saliency = segment_generation::saliency_map(
+                img,
+                options.connectivity.neighbourhood(),
+                options.wrap,
+            );
+            if let Ok(spawn_map) = image_ants::SpawnMap::new(&saliency) {
+                rules.spawn_strategy = image_ants::SpawnStrategy::GradientWeighted(spawn_map);
+            }
+        }
+        return rules;
+    };
+    // Every colony needs rules of its own, as they keep the segments of its last global update.
+    // Later rules use the parallelity of the first, so that it is only checked once.
+    let rules = scaled_rules(working_image, 1, parallelity);
+    let threads = rules.parallelity;
+    let preview = preview.map(|scale| {
+        let (width, height) = (rgb_image.width() / scale, rgb_image.height() / scale);
+        if width < 3 || height < 3 {
+            usage_and_exit(Some("Preview scale leaves less than 3x3 pixels of the image!"));
+        }
+        let preview_image =
+            imageops::resize(working_image, width, height, imageops::FilterType::Triangle);
+        return (scale, preview_image);
+    });
 
-    let input_image = ImageReader::open(image_path).unwrap().decode().unwrap();
-    let rgb_image = input_image.to_rgb8();
+    let channel_labels = segment_generation::channel_labels(multi_objective);
+    let visualize = |pheromones: &[image_ants::PheromoneImage], labels: &[&str]| -> DynamicImage {
+        let uncropped;
+        let pheromones = match roi {
+            Some(_) => {
+                uncropped = uncrop_pheromones(pheromones);
+                &uncropped
+            }
+            None => pheromones,
+        };
+        let mut result: DynamicImage = if transparent {
+            image_ants::visualize_pheromones_transparent(pheromones).into()
+        } else {
+            image_ants::visualize_pheromones(pheromones).into()
+        };
+        if legend {
+            result = image_ants::add_legend(&result.to_rgba8(), labels).into();
+            if !transparent {
+                result = result.to_rgb8().into();
+            }
+        }
+        return result;
+    };
+    let detailed_writer = detailed.then(|| BackgroundWriter::spawn(DETAILED_QUEUE_LENGTH));
 
-    let rules = segment_generation::create_rules(&rgb_image, parallelity, multi_objective);
+    // Each colony keeps track of its own progress.
+    let create_termination = || {
+        let mut criteria: Vec<Box<dyn TerminationCriterion>> = vec![];
+        if let Some(soft_timeout) = soft_timeout {
+            criteria.push(Box::new(termination::Timeout(soft_timeout)));
+        }
+        if let Some(max_attempts) = max_attempts {
+            criteria.push(Box::new(termination::MaxIterations(max_attempts)));
+        }
+        if let Some(patience) = patience {
+            criteria.push(Box::new(termination::Convergence::new(patience)));
+        }
+        if criteria.is_empty() {
+            criteria.push(Box::new(termination::MaxIterations(1)));
+        }
+        return termination::AnyOf(criteria);
+    };
+
+    let weigh = |pheromones: &mut [image_ants::PheromoneImage]| {
+        if let Some(channel_weights) = &channel_weights {
+            segment_generation::weight_channels(pheromones, channel_weights);
+        }
+    };
+    // Borrowed pheromones are only copied if the front would accept the solution.
+    let accept = |pheromones: Cow<[image_ants::PheromoneImage]>,
+                  evaluation: pareto_pheromones::Evaluation,
+                  front: &ParetoFront<pareto_pheromones::ParetoPheromones>| {
+        if evaluation.is_dominated(front) {
+            return None;
+        }
+        let mut solution = pareto_pheromones::ParetoPheromones::from_evaluation(
+            pheromones.into_owned(),
+            evaluation,
+        );
+        if compact {
+            solution.compact();
+        }
+        return Some(solution);
+    };
+    let evaluate = |mut pheromones: Cow<[image_ants::PheromoneImage]>,
+                    front: &ParetoFront<pareto_pheromones::ParetoPheromones>| {
+        if channel_weights.is_some() {
+            weigh(pheromones.to_mut());
+        }
+        let evaluation = pareto_pheromones::Evaluation::new(working_image, &pheromones, &options);
+        return accept(pheromones, evaluation, front);
+    };
 
     let start_time = Instant::now();
-    let mut solutions = ParetoFront::new();
-    let mut attempts = 0;
-    loop {
-        attempts += 1;
-        let mut pheromones = image_ants::initialize_pheromones(&mut rng, &rgb_image, &rules);
-        for step in 0..75 {
-            image_ants::run_colony_step(&mut rng, &rgb_image, &rules, &mut pheromones);
-            if detailed {
-                image_ants::visualize_pheromones(&pheromones)
-                    .save(&detailed_path.join(format!("{}-step{}.png", attempts, step)))
-                    .unwrap();
-                if pheromones.len() > 1 {
+    let steps = 75;
+    // Highest attempt number so far, recorded for later appended runs.
+    let last_number = AtomicUsize::new(previous_attempts);
+    // Runs a colony until it terminates, its attempts are numbered interleaved with the others.
+    let run_colony = |rng: &mut SmallRng,
+                      colony: usize,
+                      rules: image_ants::AntColonyRules<SmallRng>| {
+        let preview = preview.as_ref().map(|(scale, preview_image)| {
+            return (*scale, preview_image, scaled_rules(preview_image, *scale, Some(threads)));
+        });
+        let termination = create_termination();
+        let mut solutions = ParetoFront::new();
+        let mut attempts = 0;
+        // Snapshots of intermediate steps, evaluated together to make use of all threads.
+        let mut candidates = vec![];
+        let mut unweighted_candidates = vec![];
+        // Solutions are logged with the attempt they were found in.
+        let offer = |front: &mut ParetoFront<pareto_pheromones::ParetoPheromones>,
+                     solution: pareto_pheromones::ParetoPheromones,
+                     number: usize| {
+            let fields = event_log.as_ref().map(|_| EventLog::solution_fields(&solution));
+            if !push_solution(front, solution, !raw_names) {
+                return false;
+            }
+            if let (Some(event_log), Some(mut fields)) = (&event_log, fields) {
+                fields.insert(0, ("attempt", number.to_string()));
+                event_log.record("solution", &fields);
+            }
+            return true;
+        };
+        let mut stagnation = Stagnation::default();
+        // Solutions are stored with weighted channels, so warm restarts mix in the pheromones
+        // as they were before weighting, keyed by the fingerprint of the stored solution.
+        // Otherwise the weights would compound with every restart.
+        let keep_unweighted = warm_restart.is_some() && channel_weights.is_some();
+        let mut unweighted_front: HashMap<u64, Vec<image_ants::PheromoneImage>> = HashMap::new();
+        let attempt_name = |attempts: usize| {
+            if colonies == 1 {
+                return attempts.to_string();
+            }
+            return format!("{} of colony {}", attempts, colony + 1);
+        };
+        loop {
+            attempts += 1;
+            let number = previous_attempts + (attempts - 1) * colonies + colony + 1;
+            last_number.fetch_max(number, Ordering::Relaxed);
+            let mut pheromones = image_ants::initialize_pheromones(rng, working_image, &rules);
+            if let Some((scale, preview_image, preview_rules)) = &preview {
+                let mut coarse =
+                    image_ants::initialize_pheromones(rng, preview_image, preview_rules);
+                for _ in 0..(steps / *scale as usize).max(1) {
+                    image_ants::run_colony_step(rng, preview_image, preview_rules, &mut coarse);
+                }
+                let (segmented, _) = segment_generation::colorized_region_segmententation(
+                    preview_image,
+                    &coarse,
+                    0.33,
+                    &options,
+                    region_coloring,
+                    region_blend,
+                );
+                let (width, height) = rgb_image.dimensions();
+                let segmented =
+                    imageops::resize(&segmented, width, height, imageops::FilterType::Nearest);
+                pheromones = image_ants::resize_pheromones(&coarse, width, height);
+                let segmented = uncrop(&segmented, &full_image);
+                let path = preview_path.join(format!("{}.png", number));
+                save_with_retry(&path, &failed_saves, |path| segmented.save(path));
+                inform(&format!(
+                    "Preview of attempt {} saved after {:.1?}.",
+                    attempt_name(attempts),
+                    start_time.elapsed()
+                ));
+            }
+            if let Some(noise) = warm_restart {
+                let mut weight = 1.0 - noise;
+                // A stagnating colony is likely stuck on the trails it keeps reinforcing.
+                if let Some((patience, keep)) = restart_patience {
+                    if let Some(unimproved_attempts) = stagnation.restart(patience) {
+                        inform(&format!(
+                            "Restarting with fresh pheromones in attempt {}, \
+                             as the last {} attempts did not add to the pareto front.",
+                            attempt_name(attempts),
+                            unimproved_attempts
+                        ));
+                        if let Some(event_log) = &event_log {
+                            event_log.record("restart", &[("attempt", number.to_string())]);
+                        }
+                        weight = keep;
+                    }
+                }
+                if let Some(best) = pareto_pheromones::representative(&solutions) {
+                    let best_pheromones =
+                        unweighted_front.get(&best.fingerprint).unwrap_or(&best.pheromones);
+                    image_ants::mix_pheromones(&mut pheromones, best_pheromones, weight);
+                }
+            }
+            let mut improved = false;
+            for step in 0..steps {
+                image_ants::run_colony_step(rng, working_image, &rules, &mut pheromones);
+                if let Some(stats_csv) = &stats_csv {
+                    let mut stats_csv = stats_csv.lock().unwrap();
                     for (i, pheromone) in pheromones.iter().enumerate() {
-                        image_ants::visualize_pheromones(std::slice::from_ref(pheromone))
-                            .save(
-                                &detailed_path
-                                    .join(format!("{}-step{}-pheromone{}.png", attempts, step, i)),
-                            )
+                        let statistics = image_ants::PheromoneStatistics::new(pheromone);
+                        writeln!(*stats_csv, "{},{},{},{}", number, step, i, statistics.csv_row())
                             .unwrap();
                     }
                 }
+                if detailed {
+                    let writer = detailed_writer.as_ref().unwrap();
+                    let path = detailed_path.join(format!("{}-step{}.png", number, step));
+                    writer.save(path, visualize(&pheromones, &channel_labels));
+                    if pheromones.len() > 1 {
+                        for (i, pheromone) in pheromones.iter().enumerate() {
+                            let visualized =
+                                visualize(std::slice::from_ref(pheromone), &channel_labels[i..=i]);
+                            let path = detailed_path
+                                .join(format!("{}-step{}-pheromone{}.png", number, step, i));
+                            writer.save(path, visualized);
+                        }
+                    }
+                }
+                if let Some(export_path) = &export_path {
+                    for (i, pheromone) in pheromones.iter().enumerate() {
+                        let raw = image_ants::raw_pheromone(pheromone);
+                        let path =
+                            export_path.join(format!("{}-step{}-pheromone{}.exr", number, step, i));
+                        save_with_retry(&path, &failed_saves, |path| raw.save(path));
+                    }
+                }
+                if evaluate_every_step && rules.parallelity > 1 {
+                    let mut candidate = pheromones.clone();
+                    weigh(&mut candidate);
+                    candidates.push(candidate);
+                    unweighted_candidates.push(keep_unweighted.then(|| pheromones.clone()));
+                    if candidates.len() == rules.parallelity || step == steps - 1 {
+                        let evaluations = pareto_pheromones::Evaluation::batch(
+                            working_image,
+                            &candidates,
+                            &options,
+                        );
+                        // Candidates are offered in order of their steps, like without batching.
+                        for ((candidate, unweighted), evaluation) in candidates
+                            .drain(..)
+                            .zip(unweighted_candidates.drain(..))
+                            .zip(evaluations)
+                        {
+                            if let Some(solution) =
+                                accept(Cow::Owned(candidate), evaluation, &solutions)
+                            {
+                                let fingerprint = solution.fingerprint;
+                                if offer(&mut solutions, solution, number) {
+                                    improved = true;
+                                    if let Some(unweighted) = unweighted {
+                                        unweighted_front.insert(fingerprint, unweighted);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                } else if evaluate_every_step {
+                    if let Some(solution) = evaluate(Cow::Borrowed(&pheromones), &solutions) {
+                        let fingerprint = solution.fingerprint;
+                        if offer(&mut solutions, solution, number) {
+                            improved = true;
+                            if keep_unweighted {
+                                unweighted_front.insert(fingerprint, pheromones.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            if heatmap {
+                let overlay =
+                    image_ants::heatmap_overlay(&rgb_image, &pheromones, Rgb([255, 64, 0]), 192);
+                let overlay = uncrop(&overlay, &full_image);
+                let path = heatmap_path.join(format!("{}.png", number));
+                save_with_retry(&path, &failed_saves, |path| overlay.save(path));
+            }
+            if let Some(number_of_ants) = trajectories {
+                // A separate generator for each attempt, so that drawing trajectories does not
+                // change the results.
+                let mut trajectory_rng = SmallRng::seed_from_u64(trajectory_seed ^ number as u64);
+                let paths = image_ants::sample_trajectories(
+                    &mut trajectory_rng,
+                    working_image,
+                    &rules,
+                    &pheromones,
+                    number_of_ants,
+                );
+                let paths: Vec<Vec<Point>> = paths
+                    .into_iter()
+                    .map(|path| path.into_iter().map(|point| point + roi_offset).collect())
+                    .collect();
+                let drawn = image_ants::draw_trajectories(&full_image, &paths);
+                let path = trajectories_path.join(format!("{}.png", number));
+                save_with_retry(&path, &failed_saves, |path| drawn.save(path));
+            }
+            if !evaluate_every_step {
+                let unweighted = keep_unweighted.then(|| pheromones.clone());
+                if let Some(solution) = evaluate(Cow::Owned(pheromones), &solutions) {
+                    let fingerprint = solution.fingerprint;
+                    if offer(&mut solutions, solution, number) {
+                        improved = true;
+                        if let Some(unweighted) = unweighted {
+                            unweighted_front.insert(fingerprint, unweighted);
+                        }
+                    }
+                }
             }
-            if evaluate_every_step {
-                solutions
-                    .push(pareto_pheromones::ParetoPheromones::new(&rgb_image, pheromones.clone()));
+            if keep_unweighted {
+                // Solutions pushed out of the front are no longer needed for restarts.
+                unweighted_front.retain(|fingerprint, _| {
+                    solutions.iter().any(|solution| solution.fingerprint == *fingerprint)
+                });
+            }
+            stagnation.record(improved);
+            inform(&format!(
+                "Attempt {} finished after {:.1?}, the pareto front holds {} solutions.",
+                attempt_name(attempts),
+                start_time.elapsed(),
+                solutions.len()
+            ));
+            if let Some(event_log) = &event_log {
+                event_log.record(
+                    "attempt",
+                    &[
+                        ("attempt", number.to_string()),
+                        ("elapsed", json_number(start_time.elapsed().as_secs_f64())),
+                        ("front", solutions.len().to_string()),
+                    ],
+                );
+            }
+            if termination.should_stop(start_time.elapsed(), attempts, &solutions) {
+                if let Some(event_log) = &event_log {
+                    event_log.record(
+                        "stop",
+                        &[
+                            ("colony", (colony + 1).to_string()),
+                            ("attempts", attempts.to_string()),
+                            ("elapsed", json_number(start_time.elapsed().as_secs_f64())),
+                        ],
+                    );
+                }
+                info!(
+                    "Stopped after {} attempts in {:?} with {} solutions.",
+                    attempts,
+                    start_time.elapsed(),
+                    solutions.len()
+                );
+                break;
             }
         }
-        if !evaluate_every_step {
-            solutions.push(pareto_pheromones::ParetoPheromones::new(&rgb_image, pheromones));
+        return (solutions, attempts);
+    };
+    let (solutions, attempts) = if colonies == 1 {
+        run_colony(&mut rng, 0, rules)
+    } else {
+        // Seeds are derived upfront, so that each colony only depends on the seed of the run.
+        let seeds: Vec<u64> = (0..colonies).map(|_| rng.gen()).collect();
+        let mut colony_rules = vec![rules];
+        colony_rules.extend((1..colonies).map(|_| scaled_rules(working_image, 1, Some(threads))));
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = seeds
+                .iter()
+                .zip(colony_rules)
+                .enumerate()
+                .map(|(colony, (&seed, rules))| {
+                    let run_colony = &run_colony;
+                    return scope.spawn(move || {
+                        return run_colony(&mut SmallRng::seed_from_u64(seed), colony, rules);
+                    });
+                })
+                .collect();
+            return handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        });
+        // Fronts are merged in the order of the colonies, so that the result is reproducible.
+        // Dominance is not strict, so solutions with the same objectives are only kept once.
+        let mut merged = ParetoFront::new();
+        let mut total_attempts = 0;
+        for (front, attempts) in results {
+            total_attempts += attempts;
+            for solution in front {
+                push_solution(&mut merged, solution, !raw_names);
+            }
         }
-        if soft_timeout == None || start_time.elapsed() >= soft_timeout.unwrap() {
-            break;
+        inform(&format!(
+            "Merged the pareto fronts of {} colonies into {} solutions.",
+            colonies,
+            merged.len()
+        ));
+        (merged, total_attempts)
+    };
+
+    let last_number = last_number.into_inner();
+    if last_number > previous_attempts {
+        save_with_retry(&last_attempt_path, &failed_saves, |path| {
+            fs::write(path, format!("{}\n", last_number))
+        });
+    }
+
+    // Exiting early would skip writing what is still buffered.
+    if let Some(detailed_writer) = detailed_writer {
+        failed_saves.lock().unwrap().extend(detailed_writer.finish());
+    }
+    if let Some(stats_csv) = &stats_csv {
+        stats_csv.lock().unwrap().flush().unwrap();
+    }
+
+    if solutions.is_empty() {
+        let message = format!(
+            "No solutions were found after {} attempts! \
+             Try a longer timeout, more attempts or a different threshold.",
+            attempts
+        );
+        eprintln!("{}", message);
+        log_warning(&message);
+        process::exit(1);
+    }
+    let degenerate = |degeneracy| {
+        return solutions.iter().filter(|s| s.degeneracy() == Some(degeneracy)).count();
+    };
+    let collapsed = degenerate(pareto_pheromones::Degeneracy::Collapsed);
+    if collapsed > 0 {
+        let message = format!(
+            "{} of {} solutions consist of a single segment, as their contours do not close \
+             any regions. Try other thresholds with --threshold-sweep, usually lower ones, \
+             or more attempts.",
+            collapsed,
+            solutions.len()
+        );
+        warning(&message);
+    }
+    let shattered = degenerate(pareto_pheromones::Degeneracy::Shattered);
+    if shattered > 0 {
+        let message = format!(
+            "{} of {} solutions have more segments than {}% of the pixels. \
+             Try higher thresholds with --threshold-sweep.",
+            shattered,
+            solutions.len(),
+            pareto_pheromones::MAX_SEGMENT_FRACTION * 100.0
+        );
+        warning(&message);
+    }
+
+    for segments_path in [&type_1_path, &type_2_path, &type_3_path] {
+        dirbuilder.create(segments_path).unwrap();
+    }
+    // Solutions are independent of each other, so they can be written in parallel.
+    // Contours are kept lossless, as they are used like label maps.
+    let extension = output_format.extensions_str()[0];
+    // Provenance is stored in the images, so that it is not lost when they are renamed.
+    let run_metadata = [
+        ("Software", format!("{} {}", PACKAGE_NAME, env!("CARGO_PKG_VERSION"))),
+        ("Parameters", args[1..].join(" ")),
+    ];
+    let solution_metadata = |solution: &pareto_pheromones::ParetoPheromones| {
+        let mut metadata = solution.metadata();
+        metadata.extend(run_metadata.iter().cloned());
+        return metadata;
+    };
+    let solutions_list: Vec<_> = solutions.iter().collect();
+    let chunk_size = ((solutions_list.len() + threads - 1) / threads).max(1);
+    thread::scope(|scope| {
+        for chunk in solutions_list.chunks(chunk_size) {
+            let (type_1_path, type_2_path, type_3_path) =
+                (&type_1_path, &type_2_path, &type_3_path);
+            let (rgb_image, full_image) = (&rgb_image, &full_image);
+            let (uncrop, contour_background) = (&uncrop, &contour_background);
+            let solution_metadata = &solution_metadata;
+            let failed_saves = &failed_saves;
+            scope.spawn(move || {
+                for solution in chunk {
+                    let file_name = format!("{}.png", solution.file_stem(!raw_names));
+                    let image_name = format!("{}.{}", solution.file_stem(!raw_names), extension);
+                    let metadata = solution_metadata(solution);
+                    let contour =
+                        options.region_contour(rgb_image, &solution.pheromones, solution.threshold);
+                    save_solution_image(
+                        &uncrop(&contour, contour_background),
+                        &type_1_path.join(&file_name),
+                        &metadata,
+                        quality,
+                        failed_saves,
+                    );
+                    let overlayed = segment_generation::overlay_contour(
+                        rgb_image,
+                        &contour,
+                        overlay_color,
+                        overlay_opacity,
+                    );
+                    save_solution_image(
+                        &uncrop(&overlayed, full_image),
+                        &type_2_path.join(&image_name),
+                        &metadata,
+                        quality,
+                        failed_saves,
+                    );
+                    let (segmented, _) = segment_generation::colorized_region_segmententation(
+                        rgb_image,
+                        &solution.pheromones,
+                        solution.threshold,
+                        &options,
+                        region_coloring,
+                        region_blend,
+                    );
+                    save_solution_image(
+                        &uncrop(&segmented, full_image),
+                        &type_3_path.join(&image_name),
+                        &metadata,
+                        quality,
+                        failed_saves,
+                    );
+                }
+            });
+        }
+    });
+
+    // Solutions optimizing a single objective each.
+    dirbuilder.create(&best_path).unwrap();
+    let mut best_solutions = vec![
+        ("edge value", solutions.iter().max_by(|a, b| a.edge_value.total_cmp(&b.edge_value))),
+        (
+            "connectivity",
+            solutions
+                .iter()
+                .min_by(|a, b| a.connectivity_measure.total_cmp(&b.connectivity_measure)),
+        ),
+        (
+            "deviation",
+            solutions.iter().min_by(|a, b| a.overall_deviation.total_cmp(&b.overall_deviation)),
+        ),
+    ];
+    if options.entropy_objective {
+        // Every solution has an entropy with the entropy objective.
+        let entropy = |solution: &&pareto_pheromones::ParetoPheromones| {
+            return solution.overall_entropy.unwrap_or(f64::INFINITY);
+        };
+        best_solutions
+            .push(("entropy", solutions.iter().min_by(|a, b| entropy(a).total_cmp(&entropy(b)))));
+    }
+    for (objective, solution) in best_solutions {
+        if let Some(solution) = solution {
+            let (segmented, _) = segment_generation::colorized_region_segmententation(
+                &rgb_image,
+                &solution.pheromones,
+                solution.threshold,
+                &options,
+                region_coloring,
+                region_blend,
+            );
+            save_solution_image(
+                &text::add_caption(
+                    &uncrop(&segmented, &full_image),
+                    &format!("best {}", objective),
+                ),
+                &best_path.join(format!(
+                    "{}-{}.{}",
+                    objective.replace(' ', "_"),
+                    solution.file_stem(!raw_names),
+                    extension
+                )),
+                &solution_metadata(solution),
+                quality,
+                &failed_saves,
+            );
         }
     }
 
-    let mut segments_path = results_path.join("type_1_segments");
-    dirbuilder.create(&segments_path).unwrap();
-    for (i, solution) in solutions.iter().enumerate() {
-        segment_generation::contour_segmententation(&solution.pheromones, 0.33)
-            .save(&segments_path.join(format!("{}-{}.png", i, solution.stat_info())))
-            .unwrap();
+    if plot {
+        let solutions_list: Vec<_> = solutions.iter().collect();
+        for (i, solution) in solutions_list.iter().enumerate() {
+            info!("Point {} of the pareto plot is {}.", i, solution.file_stem(!raw_names));
+        }
+        let drawn = pareto_pheromones::plot_front(&solutions_list, 480, 400);
+        save_with_retry(&plot_path, &failed_saves, |path| drawn.save(path));
+    }
+
+    if let Some(target) = options.target_segments {
+        for solution in solutions.iter() {
+            inform(&format!(
+                "Threshold {:.4} segments {} into {} of the {} targeted segments.",
+                solution.threshold,
+                solution.file_stem(!raw_names),
+                solution.segment_count,
+                target.count
+            ));
+        }
+    }
+
+    let (hits, misses) = segment_generation::region_cache_statistics();
+    debug!("Region segmentation cache had {} hits and {} misses.", hits, misses);
+
+    inform(&format!(
+        "Saved {} solutions to '{}' after {:.1?}.",
+        solutions.len(),
+        results_path.display(),
+        start_time.elapsed()
+    ));
+
+    if let Some(ground_truth) = ground_truth {
+        for solution in solutions.iter() {
+            let contour =
+                options.region_contour(&rgb_image, &solution.pheromones, solution.threshold);
+            let scores = segments::BoundaryScores::new(
+                &segment_generation::contour_points(&contour, options.contour_border()),
+                &ground_truth,
+                tolerance,
+            );
+            println!(
+                "{}: precision {:.4}, recall {:.4}, F-measure {:.4}",
+                solution.file_stem(!raw_names),
+                scores.precision,
+                scores.recall,
+                scores.f_measure
+            );
+        }
+    }
+
+    let failed_saves = failed_saves.into_inner().unwrap();
+    for path in &failed_saves {
+        log_warning(&format!("Could not save '{}'.", path.display()));
+    }
+    if let Some(event_log) = &event_log {
+        event_log.record(
+            "finish",
+            &[
+                ("attempts", attempts.to_string()),
+                ("solutions", solutions.len().to_string()),
+                ("elapsed", json_number(start_time.elapsed().as_secs_f64())),
+                ("failed_saves", failed_saves.len().to_string()),
+            ],
+        );
+    }
+    if !failed_saves.is_empty() {
+        eprintln!("Could not save {} files:", failed_saves.len());
+        for path in failed_saves {
+            eprintln!("  {}", path.display());
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_json_writes_null_for_nan() {
+        let json = metrics_json(&[("rand index", 0.5), ("covering", f64::NAN)]);
+        assert_eq!(json, "{\n  \"rand_index\": 0.5,\n  \"covering\": null\n}\n");
+    }
+
+    #[test]
+    fn restart_after_patience_unimproved_attempts() {
+        let mut stagnation = Stagnation::default();
+        for _ in 0..2 {
+            stagnation.record(false);
+            assert_eq!(stagnation.restart(3), None);
+        }
+        stagnation.record(false);
+        assert_eq!(stagnation.restart(3), Some(3));
+        assert_eq!(stagnation.restart(3), None);
     }
 
-    segments_path = results_path.join("type_2_segments");
-    dirbuilder.create(&segments_path).unwrap();
-    for (i, solution) in solutions.iter().enumerate() {
-        segment_generation::overlayed_contour_segmententation(
-            &rgb_image,
-            &solution.pheromones,
-            0.33,
-        )
-        .save(&segments_path.join(format!("{}-{}.png", i, solution.stat_info())))
-        .unwrap();
-    }
-
-    segments_path = results_path.join("type_3_segments");
-    dirbuilder.create(&segments_path).unwrap();
-    for (i, solution) in solutions.iter().enumerate() {
-        segment_generation::colorized_region_segmententation(
-            &rgb_image,
-            &solution.pheromones,
-            0.33,
-        )
-        .0
-        .save(&segments_path.join(format!("{}-{}.png", i, solution.stat_info())))
-        .unwrap();
+    #[test]
+    fn improvement_resets_patience() {
+        let mut stagnation = Stagnation::default();
+        stagnation.record(false);
+        stagnation.record(false);
+        stagnation.record(true);
+        stagnation.record(false);
+        assert_eq!(stagnation.restart(2), None);
+        stagnation.record(false);
+        assert_eq!(stagnation.restart(2), Some(2));
     }
 }
@@ -1,52 +1,405 @@
 use std::collections::HashSet;
+use std::thread;
 
+use super::image_ants;
 use super::image_ants::PheromoneImage;
-use super::image_arithmetic::{color_distances, segments, Point};
-use super::segment_generation::region_segmententation;
+use super::image_arithmetic::{segments, text, ArithmeticImage, Point};
+use super::segment_generation::{
+    segment_objectives, threshold_for_segments, ObjectiveSelection, SegmentationOptions,
+};
 
-use image::RgbImage;
-use pareto_front::Dominate;
+use image::{Rgb, RgbImage};
+use pareto_front::{Dominate, ParetoFront};
 
-pub struct ParetoPheromones {
-    pub pheromones: Vec<PheromoneImage>,
+/// Objectives `(edge_value, connectivity_measure, overall_deviation, overall_entropy)`.
+type Objectives = (f64, f64, f64, Option<f64>);
+
+/// Whether the objectives `a` are at least as good as `b` in each of the selected objectives.
+/// The optional entropy takes part whenever it is evaluated.
+fn dominates(selection: ObjectiveSelection, a: &Objectives, b: &Objectives) -> bool {
+    return (!selection.edge_value || a.0 >= b.0)
+        && (!selection.connectivity_measure || a.1 <= b.1)
+        && (!selection.overall_deviation || a.2 <= b.2)
+        && a.3 <= b.3;
+}
+
+/// Index of the best of the objectives, only ranking the selected ones.
+/// Candidates are ranked per objective by how many others are strictly better,
+/// the one with the lowest sum of ranks wins, earlier candidates winning ties.
+/// Ranks do not depend on the scale of the objectives, so each counts equally.
+/// The winner is never dominated by another candidate.
+fn lowest_rank_sum(objectives: &[Objectives], selection: ObjectiveSelection) -> Option<usize> {
+    let rank = |candidate: &Objectives| {
+        return objectives
+            .iter()
+            .map(|other| {
+                (selection.edge_value && other.0 > candidate.0) as usize
+                    + (selection.connectivity_measure && other.1 < candidate.1) as usize
+                    + (selection.overall_deviation && other.2 < candidate.2) as usize
+                    + (other.3 < candidate.3) as usize
+            })
+            .sum::<usize>();
+    };
+    return (0..objectives.len()).min_by_key(|i| rank(&objectives[*i]));
+}
+
+/// The solution balancing all objectives best, see `lowest_rank_sum`.
+pub fn representative(front: &ParetoFront<ParetoPheromones>) -> Option<&ParetoPheromones> {
+    let solutions: Vec<_> = front.iter().collect();
+    let objectives: Vec<_> = solutions.iter().map(|s| s.objective_values()).collect();
+    let selection = solutions.first()?.active_objectives;
+    return lowest_rank_sum(&objectives, selection).map(|i| solutions[i]);
+}
+
+/// Segments and objectives of pheromones, computed without taking ownership of them.
+/// Allows to check whether a solution is worth keeping before the pheromones are copied.
+pub struct Evaluation {
+    /// Threshold the segments were extracted with.
+    pub threshold: f32,
     pub segments: Vec<HashSet<Point>>,
     pub edge_value: f64,
     pub connectivity_measure: f64,
     pub overall_deviation: f64,
+    /// Only evaluated if `SegmentationOptions::entropy_objective` is set.
+    pub overall_entropy: Option<f64>,
+    /// Objectives taking part in comparisons, see `SegmentationOptions::objectives`.
+    pub active_objectives: ObjectiveSelection,
 }
 
-impl ParetoPheromones {
-    pub fn new(image: &RgbImage, pheromones: Vec<PheromoneImage>) -> Self {
-        let (_, segments) = region_segmententation(&pheromones, 0.33);
-        let edge_value = segments::edge_value(image, &segments, &color_distances::euclidean);
-        let connectivity_measure =
-            segments::connectivity_measure(image, &segments, &color_distances::euclidean);
-        let overall_deviation =
-            segments::overall_deviation(image, &segments, &color_distances::euclidean);
+impl Evaluation {
+    /// Evaluates the segmentation at the default threshold,
+    /// at the one closest to `SegmentationOptions::target_segments` if given,
+    /// or the best one of the sweep, if `SegmentationOptions::threshold_sweep` is given.
+    pub fn new(
+        image: &RgbImage, pheromones: &[PheromoneImage], options: &SegmentationOptions,
+    ) -> Self {
+        if let Some(target) = options.target_segments {
+            let (threshold, _) = threshold_for_segments(
+                pheromones,
+                target,
+                &options.edge_kernel,
+                options.contour_border(),
+                options.connectivity.regions(),
+            );
+            return Self::at_threshold(image, pheromones, threshold, options);
+        }
+        return match options.threshold_sweep {
+            Some(sweep) => Self::sweep(image, pheromones, &sweep.thresholds(), options),
+            None => Self::at_threshold(image, pheromones, 0.33, options),
+        };
+    }
+
+    pub fn at_threshold(
+        image: &RgbImage, pheromones: &[PheromoneImage], threshold: f32,
+        options: &SegmentationOptions,
+    ) -> Self {
+        let segments = options.region_segments(image, pheromones, threshold);
+        let (edge_value, connectivity_measure, overall_deviation) =
+            segment_objectives(image, &segments, options);
+        let overall_entropy =
+            options.entropy_objective.then(|| segments::overall_entropy(image, &segments));
         return Self {
-            pheromones,
+            threshold,
             segments,
             edge_value,
             connectivity_measure,
             overall_deviation,
+            overall_entropy,
+            active_objectives: options.objectives,
         };
     }
 
-    pub fn stat_info(&self) -> String {
-        format!(
-            "segs{}-e{:.2E}-c{:.2E}-d{:.2E}",
-            self.segments.len(),
+    /// Evaluates the segmentation at each of the thresholds and keeps the best one,
+    /// see `lowest_rank_sum`, earlier thresholds winning ties.
+    pub fn sweep(
+        image: &RgbImage, pheromones: &[PheromoneImage], thresholds: &[f32],
+        options: &SegmentationOptions,
+    ) -> Self {
+        let mut candidates: Vec<_> = thresholds
+            .iter()
+            .map(|threshold| Self::at_threshold(image, pheromones, *threshold, options))
+            .collect();
+        let objectives: Vec<_> = candidates.iter().map(|c| c.objective_values()).collect();
+        return candidates.swap_remove(lowest_rank_sum(&objectives, options.objectives).unwrap());
+    }
+
+    /// Evaluates the candidates in parallel, one thread each, as they are independent.
+    /// Each evaluation runs on a single thread, so results do not depend on the batch.
+    pub fn batch(
+        image: &RgbImage, candidates: &[Vec<PheromoneImage>], options: &SegmentationOptions,
+    ) -> Vec<Self> {
+        return thread::scope(|scope| {
+            let threads: Vec<_> = candidates
+                .iter()
+                .map(|pheromones| scope.spawn(move || Self::new(image, pheromones, options)))
+                .collect();
+            return threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        });
+    }
+
+    /// Whether the front would reject a solution with these objectives,
+    /// i.e. whether any of its solutions dominates them.
+    pub fn is_dominated(&self, front: &ParetoFront<ParetoPheromones>) -> bool {
+        let objectives = self.objective_values();
+        return front.iter().any(|solution| {
+            dominates(self.active_objectives, &solution.objective_values(), &objectives)
+        });
+    }
+
+    fn objective_values(&self) -> Objectives {
+        return (
+            self.edge_value,
+            self.connectivity_measure,
+            self.overall_deviation,
+            self.overall_entropy,
+        );
+    }
+}
+
+/// Ways in which segmentations fail to find meaningful segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Degeneracy {
+    /// The whole image is a single segment, as the contours do not close any regions.
+    /// Lower thresholds turn weaker trails into contours as well.
+    Collapsed,
+    /// The image is shattered into more segments than `MAX_SEGMENT_FRACTION` of its pixels.
+    /// Higher thresholds only keep the stronger trails as contours.
+    Shattered,
+}
+
+/// Fraction of the pixels beyond which the number of segments is considered degenerate.
+pub const MAX_SEGMENT_FRACTION: f64 = 0.5;
+
+pub struct ParetoPheromones {
+    pub pheromones: Vec<PheromoneImage>,
+    /// Threshold the segments were extracted with, to be used for outputs as well.
+    pub threshold: f32,
+    pub segments: Vec<HashSet<Point>>,
+    pub segment_count: usize,
+    pub edge_value: f64,
+    pub connectivity_measure: f64,
+    pub overall_deviation: f64,
+    /// Optional fourth objective, see `segments::overall_entropy`.
+    pub overall_entropy: Option<f64>,
+    /// Objectives taking part in comparisons, see `SegmentationOptions::objectives`.
+    pub active_objectives: ObjectiveSelection,
+    /// Hash of the pheromone content, stable across runs.
+    pub fingerprint: u64,
+}
+
+impl ParetoPheromones {
+    pub fn new(
+        image: &RgbImage, pheromones: Vec<PheromoneImage>, options: &SegmentationOptions,
+    ) -> Self {
+        let evaluation = Evaluation::new(image, &pheromones, options);
+        return Self::from_evaluation(pheromones, evaluation);
+    }
+
+    /// Combines the pheromones with their previously computed evaluation.
+    pub fn from_evaluation(pheromones: Vec<PheromoneImage>, evaluation: Evaluation) -> Self {
+        let fingerprint = image_ants::pheromone_fingerprint(&pheromones);
+        return Self {
+            pheromones,
+            threshold: evaluation.threshold,
+            segment_count: evaluation.segments.len(),
+            segments: evaluation.segments,
+            edge_value: evaluation.edge_value,
+            connectivity_measure: evaluation.connectivity_measure,
+            overall_deviation: evaluation.overall_deviation,
+            overall_entropy: evaluation.overall_entropy,
+            active_objectives: evaluation.active_objectives,
+            fingerprint,
+        };
+    }
+
+    fn objective_values(&self) -> Objectives {
+        return (
             self.edge_value,
             self.connectivity_measure,
-            self.overall_deviation
-        )
+            self.overall_deviation,
+            self.overall_entropy,
+        );
+    }
+
+    /// Reduces memory usage by keeping only what is needed to generate the segmentation outputs.
+    /// Channels are summed up, because contours only depend on their sum,
+    /// and segments are dropped, because they can be recomputed from the pheromones.
+    /// The individual channels are lost, and segments have to be recomputed when needed.
+    pub fn compact(&mut self) {
+        if self.pheromones.len() > 1 {
+            let mut summed = self.pheromones[0].clone();
+            for pheromone in &self.pheromones[1..] {
+                summed.add(pheromone);
+            }
+            self.pheromones = vec![summed];
+        }
+        self.segments = vec![];
+    }
+
+    pub fn stat_info(&self) -> String {
+        let info = format!(
+            "segs{}-e{:.2E}-c{:.2E}-d{:.2E}",
+            self.segment_count, self.edge_value, self.connectivity_measure, self.overall_deviation
+        );
+        match self.overall_entropy {
+            Some(entropy) => format!("{}-h{:.2E}", info, entropy),
+            None => info,
+        }
+    }
+
+    /// Like `stat_info`, but with objectives divided by the number of pixels,
+    /// so that solutions for images of different sizes can be compared.
+    pub fn normalized_stat_info(&self) -> String {
+        let (width, height) = self.pheromones[0].dimensions();
+        let pixels = (width * height).max(1) as f64;
+        let info = format!(
+            "segs{}-e{:.3}-c{:.3}-d{:.3}",
+            self.segment_count,
+            self.edge_value / pixels,
+            self.connectivity_measure / pixels,
+            self.overall_deviation / pixels
+        );
+        match self.overall_entropy {
+            Some(entropy) => format!("{}-h{:.3}", info, entropy / pixels),
+            None => info,
+        }
+    }
+
+    /// How the segmentation failed, if it did.
+    pub fn degeneracy(&self) -> Option<Degeneracy> {
+        let (width, height) = self.pheromones[0].dimensions();
+        if self.segment_count == 1 {
+            return Some(Degeneracy::Collapsed);
+        }
+        if self.segment_count as f64 > MAX_SEGMENT_FRACTION * (width * height) as f64 {
+            return Some(Degeneracy::Shattered);
+        }
+        return None;
+    }
+
+    /// Objectives and other properties of the solution, to be stored alongside its images.
+    pub fn metadata(&self) -> Vec<(&'static str, String)> {
+        let mut metadata = vec![
+            ("Segments", self.segment_count.to_string()),
+            ("Threshold", self.threshold.to_string()),
+            ("Edge value", self.edge_value.to_string()),
+            ("Connectivity measure", self.connectivity_measure.to_string()),
+            ("Overall deviation", self.overall_deviation.to_string()),
+        ];
+        if let Some(entropy) = self.overall_entropy {
+            metadata.push(("Overall entropy", entropy.to_string()));
+        }
+        metadata.push(("Fingerprint", format!("{:016x}", self.fingerprint)));
+        return metadata;
+    }
+
+    /// Name that only depends on the solution itself,
+    /// not on its position within the pareto front.
+    /// Objectives are given per pixel if `normalized`, otherwise as raw sums.
+    pub fn file_stem(&self, normalized: bool) -> String {
+        let stats = if normalized {
+            self.normalized_stat_info()
+        } else {
+            self.stat_info()
+        };
+        return format!("{}-{:016x}", stats, self.fingerprint);
     }
 }
 
 impl Dominate for ParetoPheromones {
     fn dominate(&self, other: &Self) -> bool {
-        self.edge_value >= other.edge_value
-            && self.connectivity_measure <= other.connectivity_measure
-            && self.overall_deviation <= other.overall_deviation
+        dominates(self.active_objectives, &self.objective_values(), &other.objective_values())
+    }
+}
+
+/// Scatter plot of the objectives per pixel of the solutions,
+/// with the edge value along the x-axis, the connectivity measure along the y-axis
+/// and the overall deviation as color, from blue for the lowest to red for the highest.
+/// Each point is labeled with the index of its solution,
+/// the ranges of the objectives are listed below the plot.
+pub fn plot_front(solutions: &[&ParetoPheromones], width: u32, height: u32) -> RgbImage {
+    let scale = 2;
+    let line_height = (text::GLYPH_HEIGHT + 2) * scale;
+    let radius = 3;
+    let mut canvas = RgbImage::new(width, height);
+    let (left, top) = (scale, line_height);
+    let (right, bottom) = (width.saturating_sub(scale), height.saturating_sub(3 * line_height));
+    let axis_color = Rgb([128, 128, 128]);
+    for y in top..bottom {
+        canvas.put_pixel(left, y, axis_color);
+    }
+    for x in left..right {
+        canvas.put_pixel(x, bottom, axis_color);
+    }
+
+    let per_pixel = |solution: &ParetoPheromones| {
+        let (width, height) = solution.pheromones[0].dimensions();
+        let pixels = (width * height).max(1) as f64;
+        return [
+            solution.edge_value / pixels,
+            solution.connectivity_measure / pixels,
+            solution.overall_deviation / pixels,
+        ];
+    };
+    let objectives: Vec<[f64; 3]> = solutions.iter().map(|solution| per_pixel(solution)).collect();
+    let ranges: Vec<(f64, f64)> = (0..3)
+        .map(|i| {
+            return objectives.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |range, values| {
+                (range.0.min(values[i]), range.1.max(values[i]))
+            });
+        })
+        .collect();
+    // Solutions with equal values are placed in the middle of the axis.
+    let position = |value: f64, (min, max): (f64, f64)| {
+        return if max > min {
+            (value - min) / (max - min)
+        } else {
+            0.5
+        };
+    };
+
+    let inset = radius + 2;
+    let (plot_width, plot_height) = (
+        (right - left).saturating_sub(2 * inset) as f64,
+        (bottom - top).saturating_sub(2 * inset) as f64,
+    );
+    for (i, values) in objectives.iter().enumerate() {
+        let x = left + inset + (position(values[0], ranges[0]) * plot_width).round() as u32;
+        let y = bottom - inset - (position(values[1], ranges[1]) * plot_height).round() as u32;
+        let t = position(values[2], ranges[2]);
+        let color = Rgb([(255.0 * t) as u8, 64, (255.0 * (1.0 - t)) as u8]);
+        for px in x.saturating_sub(radius)..=x + radius {
+            for py in y.saturating_sub(radius)..=y + radius {
+                if let Some(pixel) = canvas.get_pixel_mut_checked(px, py) {
+                    *pixel = color;
+                }
+            }
+        }
+        // Labels go to the left of points close to the right edge, so they are not cut off.
+        let label = i.to_string();
+        let label_width = text::text_width(&label, scale);
+        let label_x = if x + inset + label_width > right {
+            x.saturating_sub(inset + label_width)
+        } else {
+            x + inset
+        };
+        text::draw_text(
+            &mut canvas,
+            &label,
+            label_x,
+            y.saturating_sub(inset),
+            scale,
+            Rgb([255; 3]),
+        );
+    }
+
+    if !objectives.is_empty() {
+        let names = ["x edge value", "y connectivity", "color deviation"];
+        for (i, (name, (min, max))) in names.iter().zip(&ranges).enumerate() {
+            let caption = format!("{} {:.3} to {:.3}", name, min, max);
+            let y = bottom + scale + i as u32 * line_height;
+            text::draw_text(&mut canvas, &caption, left, y, scale, Rgb([255; 3]));
+        }
     }
+    return canvas;
 }
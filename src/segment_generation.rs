@@ -1,23 +1,105 @@
 //! Provides functionality to segment images with ant colony optimization.
 
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::sync::Mutex;
 
+use super::image_ants;
 use super::image_ants::{AntColonyRules, PheromoneImage, UpdateFunction};
 use super::image_arithmetic;
-use super::image_arithmetic::{color_distances, segments, ArithmeticImage, Point};
+use super::image_arithmetic::{
+    color_distances, segments, segments::Segmentation, ArithmeticImage, ColorSpaceDistance,
+    Connectivity, Point,
+};
 
 use cached::proc_macro::cached;
-use image::{imageops, DynamicImage, Pixel, RgbImage, Rgba, RgbaImage};
+use cached::Cached;
+use image::{imageops, DynamicImage, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
 use rand;
 
-pub fn contour_segmententation(pheromones: &[PheromoneImage], threshold: f32) -> RgbImage {
-    let mut segmentation = pheromones[0].clone();
+/// Connectivity of the contour lines produced by `extract_edges`.
+/// The default Laplace kernel considers all eight neighbours.
+/// Other kernels produce thinner lines, which are still closed for regions of the complement.
+pub const CONTOUR_CONNECTIVITY: Connectivity = Connectivity::Eight;
+
+/// Neighbourhoods used throughout the segmentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineConnectivity {
+    /// Ants move and objectives are measured among all eight neighbours,
+    /// while regions are filled among orthogonal neighbours only,
+    /// so that they cannot leak through the contours, see `CONTOUR_CONNECTIVITY`.
+    Legacy,
+    /// The same connectivity for ant movement, objectives and regions.
+    /// With eight-connected regions, regions may leak through diagonal steps of the contours.
+    Uniform(Connectivity),
+}
+
+impl PipelineConnectivity {
+    /// Neighbourhood ants move in and objectives are measured in.
+    pub fn neighbourhood(self) -> Connectivity {
+        return match self {
+            Self::Legacy => Connectivity::Eight,
+            Self::Uniform(connectivity) => connectivity,
+        };
+    }
+
+    /// Connectivity regions between the contours are filled with.
+    pub fn regions(self) -> Connectivity {
+        return match self {
+            Self::Legacy => CONTOUR_CONNECTIVITY.complement(),
+            Self::Uniform(connectivity) => connectivity,
+        };
+    }
+}
+
+/// Scales each channel by its weight.
+/// As contours are formed from the sum of all channels,
+/// this emphasizes some channels over others when thresholding.
+pub fn weight_channels(pheromones: &mut [PheromoneImage], weights: &[f32]) {
+    for (pheromone, weight) in pheromones.iter_mut().zip(weights) {
+        pheromone.mul_scalar(*weight);
+    }
+}
+
+/// How contours are handled at the edges of the image.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ContourBorder {
+    /// A contour is added along the edges of the image, so every segment is closed.
+    /// Segments touching the edge are separated from it by this artificial boundary,
+    /// which also splits regions that only connect along the outermost pixels.
+    Closed,
+    /// Segments extend up to the edges of the image.
+    /// Edges are found as if the image continued beyond them with its outermost pixels,
+    /// so contours reach the edge instead of stopping one pixel short.
+    /// Segments are only closed where the contours meet the edge.
+    Open,
+    /// Contours continue across the edges onto the opposite side, see `SegmentationOptions::wrap`.
+    Wrapped,
+}
+
+/// Sum of all channels, which contours are found in.
+/// Channels contribute equally, unless they are weighted beforehand with `weight_channels`.
+pub fn sum_channels(pheromones: &[PheromoneImage]) -> PheromoneImage {
+    let mut sum = pheromones[0].clone();
     for pheromone in &pheromones[1..] {
-        segmentation.add(pheromone);
+        sum.add(pheromone);
     }
-    segmentation = extract_edges(&segmentation, threshold);
+    return sum;
+}
+
+/// Thresholds the sum of all channels to find contours.
+/// Channels contribute equally, unless they are weighted beforehand with `weight_channels`.
+pub fn contour_segmententation(
+    pheromones: &[PheromoneImage], threshold: f32, kernel: &[f32; 9], border: ContourBorder,
+) -> RgbImage {
+    let mut segmentation = extract_edges(&sum_channels(pheromones), threshold, kernel, border);
     imageops::invert(&mut segmentation);
+    if border != ContourBorder::Closed {
+        return DynamicImage::from(segmentation).to_rgb8();
+    }
     // Add border to enforce closed segments.
     let w = segmentation.width();
     let h = segmentation.height();
@@ -27,69 +109,654 @@ pub fn contour_segmententation(pheromones: &[PheromoneImage], threshold: f32) ->
     return canvas;
 }
 
+/// Pixels on the contours of `contour_segmententation`,
+/// excluding the border that is added to enforce closed segments.
+pub fn contour_points(contour: &RgbImage, border: ContourBorder) -> HashSet<Point> {
+    let mut points = segments::select_points(contour, |pixel| pixel.0[0] < 128);
+    if border != ContourBorder::Closed {
+        return points;
+    }
+    let corner_a = Point { x: 1, y: 1 };
+    let corner_b = Point { x: contour.width() as i64 - 2, y: contour.height() as i64 - 2 };
+    points.retain(|point| point.is_within_rectangle(&corner_a, &corner_b));
+    return points;
+}
+
+pub const DEFAULT_OVERLAY_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+pub const DEFAULT_OVERLAY_OPACITY: u8 = 170;
+
+/// Contour separating the segments, dark on a white background like `contour_segmententation`.
+/// Pixels belonging to no segment are part of the contour, as are pixels of a segment
+/// whose right or lower neighbour belongs to another one, so that segments touching directly
+/// are separated by a line of a single pixel as well.
+pub fn segments_contour(
+    segments: &[HashSet<Point>], width: u32, height: u32, wrap: bool,
+) -> RgbImage {
+    let mut labels = vec![None; (width * height) as usize];
+    for (i, segment) in segments.iter().enumerate() {
+        for point in segment {
+            labels[(point.y * width as i64 + point.x) as usize] = Some(i);
+        }
+    }
+    let label = |point: Point| {
+        return point.within(width, height, wrap).and_then(|point| {
+            return labels[(point.y * width as i64 + point.x) as usize];
+        });
+    };
+    return RgbImage::from_fn(width, height, |x, y| {
+        let point = Point::from((x, y));
+        let on_contour = match label(point) {
+            None => true,
+            Some(i) => [Point { x: 1, y: 0 }, Point { x: 0, y: 1 }]
+                .iter()
+                .any(|&direction| label(point + direction).is_some_and(|j| j != i)),
+        };
+        if on_contour {
+            return Rgb([0, 0, 0]);
+        }
+        return Rgb([255, 255, 255]);
+    });
+}
+
+/// Draws the contours on top of the image with the given color and opacity.
 pub fn overlayed_contour_segmententation(
-    img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32,
+    img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32, kernel: &[f32; 9],
+    border: ContourBorder, color: Rgb<u8>, opacity: u8,
+) -> RgbImage {
+    let contour = contour_segmententation(pheromones, threshold, kernel, border);
+    return overlay_contour(img, &contour, color, opacity);
+}
+
+/// Draws the contour image on top of the image with the given color and opacity,
+/// darker pixels of the contour being more opaque.
+pub fn overlay_contour(
+    img: &RgbImage, contour: &RgbImage, color: Rgb<u8>, opacity: u8,
 ) -> RgbImage {
-    let p = contour_segmententation(pheromones, threshold);
-    let colored_contour = RgbaImage::from_fn(p.width(), p.height(), |x, y| {
-        Rgba([0, 255, 0, (255 - p.get_pixel(x, y).0[0]) / 3 * 2])
+    let colored_contour = RgbaImage::from_fn(contour.width(), contour.height(), |x, y| {
+        let strength = (255 - contour.get_pixel(x, y).0[0]) as u16;
+        Rgba([
+            color.0[0],
+            color.0[1],
+            color.0[2],
+            (strength * opacity as u16 / 255) as u8,
+        ])
     });
     let mut canvas = DynamicImage::from(img.clone());
     imageops::overlay(&mut canvas, &colored_contour, 0, 0);
     return canvas.to_rgb8();
 }
 
-/// Cached calculation of segments from pheromones.
-// #[cached(size = 64, convert = r#"{ format!("{:p}", pheromones) }"#, key = "String", sync_writes = true)]
+/// Hash of the arguments of `cached_region_segmententation`, including the content of the
+/// pheromones, so that pheromones changed in place are not mistaken for the ones cached before.
+fn segmentation_key(
+    pheromones: &[PheromoneImage], threshold: f32, kernel: &[f32; 9], border: ContourBorder,
+    connectivity: Connectivity,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image_ants::pheromone_fingerprint(pheromones).hash(&mut hasher);
+    threshold.to_bits().hash(&mut hasher);
+    kernel.map(f32::to_bits).hash(&mut hasher);
+    border.hash(&mut hasher);
+    connectivity.hash(&mut hasher);
+    return hasher.finish();
+}
+
+/// Hits and misses of the cache of `cached_region_segmententation` so far.
+pub fn region_cache_statistics() -> (u64, u64) {
+    let cache = REGION_SEGMENTATION_CACHE.lock().unwrap();
+    return (cache.cache_hits().unwrap_or(0), cache.cache_misses().unwrap_or(0));
+}
+
+/// Like `region_segmententation`, but remembers the most recent results by pheromone content.
+/// Hits occur when the same pheromones are segmented again at the same threshold,
+/// e.g. when a threshold found by `threshold_for_segments` is used for the evaluation,
+/// or when several outputs are generated for a solution.
+/// Every result holds the points of all segments, about as large as the image itself,
+/// and repeated segmentations follow each other closely, so only a few results are kept.
+/// Global updates segment pheromones that change with every step, so they are not cached.
+/// With 3 attempts on a 100x80 image, 6 of 9 lookups hit, 7 of 10 with a refine pass,
+/// but only 7 of 36 with a target number of segments, as its threshold search rarely repeats.
+#[cached(
+    name = "REGION_SEGMENTATION_CACHE",
+    size = 4,
+    key = "u64",
+    convert = r#"{ segmentation_key(pheromones, threshold, kernel, border, connectivity) }"#
+)]
+pub fn cached_region_segmententation(
+    pheromones: &[PheromoneImage], threshold: f32, kernel: &[f32; 9], border: ContourBorder,
+    connectivity: Connectivity,
+) -> (RgbImage, Segmentation) {
+    return region_segmententation(pheromones, threshold, kernel, border, connectivity);
+}
+
+/// Calculate segments from pheromones.
 pub fn region_segmententation(
-    pheromones: &[PheromoneImage], threshold: f32,
-) -> (RgbImage, Vec<HashSet<Point>>) {
-    return segments::extract_segments(&contour_segmententation(pheromones, threshold));
+    pheromones: &[PheromoneImage], threshold: f32, kernel: &[f32; 9], border: ContourBorder,
+    connectivity: Connectivity,
+) -> (RgbImage, Segmentation) {
+    let (regions, segments) = segments::extract_segments(
+        &contour_segmententation(pheromones, threshold, kernel, border),
+        connectivity,
+        border == ContourBorder::Wrapped,
+    );
+    let (width, height) = regions.dimensions();
+    return (regions, Segmentation::new(segments, width, height));
 }
 
+/// Number of halvings of the threshold interval when searching for a number of segments,
+/// after which thresholds differ by less than 1e-4.
+const SEGMENT_SEARCH_STEPS: usize = 14;
+
+/// Threshold whose segmentation has the number of segments closest to the target,
+/// and that number of segments, see `SegmentationOptions::target_segments`.
+/// Higher thresholds keep fewer trails as contours and thus tend to produce fewer segments,
+/// so the threshold is found by binary search in [0, 1].
+/// The search stops as soon as the number is within the tolerance of the target.
+/// As the relationship is not strictly monotonic, the closest of all tried thresholds is
+/// returned otherwise, the one tried first winning ties.
+pub fn threshold_for_segments(
+    pheromones: &[PheromoneImage], target: TargetSegments, kernel: &[f32; 9],
+    border: ContourBorder, connectivity: Connectivity,
+) -> (f32, usize) {
+    let count = |threshold: f32| {
+        return cached_region_segmententation(pheromones, threshold, kernel, border, connectivity)
+            .1
+            .segments
+            .len();
+    };
+    let (mut low, mut high) = (0.0f32, 1.0f32);
+    let mut closest: Option<(f32, usize)> = None;
+    for _ in 0..SEGMENT_SEARCH_STEPS {
+        let threshold = (low + high) / 2.0;
+        let segments = count(threshold);
+        let distance = segments.abs_diff(target.count);
+        if closest.is_none_or(|(_, closest)| distance < closest.abs_diff(target.count)) {
+            closest = Some((threshold, segments));
+        }
+        if distance <= target.tolerance {
+            break;
+        }
+        if segments > target.count {
+            low = threshold;
+        } else {
+            high = threshold;
+        }
+    }
+    return closest.unwrap();
+}
+
+/// Objectives solutions are compared by, and in multi objective optimization,
+/// those whose pheromone channels are updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectiveSelection {
+    pub edge_value: bool,
+    pub connectivity_measure: bool,
+    /// Has no pheromone channel, so it only takes part in comparing solutions.
+    pub overall_deviation: bool,
+}
+
+impl Default for ObjectiveSelection {
+    fn default() -> Self {
+        return Self { edge_value: true, connectivity_measure: true, overall_deviation: true };
+    }
+}
+
+/// Color distances the objectives of evaluated solutions are computed with.
+/// The current formulations of the connectivity measure do not depend on colors,
+/// see `segments::ConnectivityWeighting`, so it has no distance.
+#[derive(Clone, Copy)]
+pub struct ObjectiveDistances {
+    pub edge_value: &'static ColorSpaceDistance,
+    pub overall_deviation: &'static ColorSpaceDistance,
+}
+
+// Distances are closures, which cannot be printed.
+impl std::fmt::Debug for ObjectiveDistances {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.debug_struct("ObjectiveDistances").finish_non_exhaustive();
+    }
+}
+
+impl Default for ObjectiveDistances {
+    fn default() -> Self {
+        return Self {
+            edge_value: &color_distances::euclidean,
+            overall_deviation: &color_distances::euclidean,
+        };
+    }
+}
+
+/// Objectives `(edge_value, connectivity_measure, overall_deviation)` of the given segments,
+/// as compared by the pareto front.
+pub fn segment_objectives(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, options: &SegmentationOptions,
+) -> (f64, f64, f64) {
+    let distances = &options.objective_distances;
+    let weighting = options.connectivity_weighting;
+    let edge_weighting = options.edge_weighting;
+    let neighbourhood = options.connectivity.neighbourhood();
+    let edge_dist = options.color_distance(distances.edge_value);
+    let deviation_dist = options.color_distance(distances.overall_deviation);
+    let connectivity_dist = &color_distances::euclidean;
+    return (
+        edge_weighting.value(img, segments, &edge_dist, neighbourhood, options.wrap),
+        weighting.measure(img, segments, connectivity_dist, neighbourhood, options.wrap),
+        segments::overall_deviation(img, segments, &deviation_dist),
+    );
+}
+
+/// Edge value of a segmentation given by its contour, as computed for `segment_objectives`.
+/// Only the edge value is meaningful without regions, see `segments::contour_edge_value`.
+pub fn contour_objective(
+    img: &RgbImage, contour: &HashSet<Point>, options: &SegmentationOptions,
+) -> f64 {
+    let dist = options.color_distance(options.objective_distances.edge_value);
+    let neighbourhood = options.connectivity.neighbourhood();
+    return segments::contour_edge_value(img, contour, &dist, neighbourhood, options.wrap);
+}
+
+/// Objectives of the segmentation of the pheromones, see `segment_objectives`.
+/// Neither the pheromones nor the segments are retained,
+/// which makes this suitable for scoring many candidates, e.g. during a parameter search.
+pub fn objectives(
+    img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32, options: &SegmentationOptions,
+) -> (f64, f64, f64) {
+    let (_, segmentation) = region_segmententation(
+        pheromones,
+        threshold,
+        &options.edge_kernel,
+        options.contour_border(),
+        options.connectivity.regions(),
+    );
+    return segment_objectives(img, &segmentation.segments, options);
+}
+
+/// Colors each segment with its color, blended with the original pixels.
+/// A blend factor of 0 keeps the original image, 1 results in flat colors.
+/// Segments are extracted as for evaluation, see `SegmentationOptions::region_segments`.
 pub fn colorized_region_segmententation(
-    img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32,
+    img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32, options: &SegmentationOptions,
+    coloring: SegmentColoring, blend: f32,
 ) -> (RgbImage, Vec<HashSet<Point>>) {
-    let (mut segmented, segments) = region_segmententation(pheromones, threshold);
-    for points in &segments {
-        let color = image_arithmetic::mean_color(&img, points);
-        points.iter().for_each(|p| *p.get_pixel_mut(&mut segmented) = color);
+    let segments = options.region_segments(img, pheromones, threshold);
+    let mut colorized = colorize_segments(img, &segments, coloring);
+    if blend < 1.0 {
+        for point in segments.iter().flatten() {
+            let original = point.get_pixel(img);
+            point.get_pixel_mut(&mut colorized).apply2(original, |fill, original| {
+                (blend * fill as f32 + (1.0 - blend) * original as f32).round() as u8
+            });
+        }
+    }
+    return (colorized, segments);
+}
+
+/// Color each segment is filled with.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentColoring {
+    /// Mean color of its pixels.
+    Mean,
+    /// Most frequent color of its pixels.
+    /// Keeps the palette of illustration-like images, where the mean washes out colors.
+    Mode,
+    /// Distinct color derived from its position, see `image_arithmetic::generate_stable_color`.
+    /// Allows to compare segmentations of different runs side by side.
+    Stable,
+    /// Distinct color of its index in `SegmentationOptions::segment_order`,
+    /// see `image_arithmetic::generate_unique_color`.
+    Index,
+}
+
+impl SegmentColoring {
+    /// Color of the segment with the given index.
+    pub fn color(self, img: &RgbImage, index: usize, points: &HashSet<Point>) -> Rgb<u8> {
+        return match self {
+            Self::Mean => image_arithmetic::mean_color(img, points),
+            Self::Mode => image_arithmetic::mode_color(img, points),
+            Self::Stable => image_arithmetic::generate_stable_color(points),
+            Self::Index => image_arithmetic::generate_unique_color(index),
+        };
+    }
+}
+
+/// Colors each segment with the color of its pixels in the image.
+/// Pixels not belonging to any segment, i.e. contours, are black.
+pub fn colorize_segments(
+    img: &RgbImage, segments: &Vec<HashSet<Point>>, coloring: SegmentColoring,
+) -> RgbImage {
+    let mut colorized = RgbImage::new(img.width(), img.height());
+    for (index, points) in segments.iter().enumerate() {
+        let color = coloring.color(img, index, points);
+        points.iter().for_each(|p| *p.get_pixel_mut(&mut colorized) = color);
+    }
+    return colorized;
+}
+
+/// Tunable parameters of the segmentation rules.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentationOptions {
+    /// Normalize pheromones to sum up to 1 after each global update,
+    /// instead of scaling them by their maximum.
+    ///
+    /// Edge extraction and `visualize_pheromones` first add up all channels
+    /// and rescale the result by its maximum, so with this option
+    /// a channel's weight in the combination depends on how concentrated it is,
+    /// rather than on a fixed per-channel peak.
+    /// As the individual values become very small, ants will follow the trails less.
+    pub normalize_sum: bool,
+    /// Formulation of the connectivity measure used for updates and evaluation.
+    pub connectivity_weighting: segments::ConnectivityWeighting,
+    /// Formulation of the edge value used for updates and evaluation.
+    pub edge_weighting: segments::EdgeWeighting,
+    /// Skip the region segmentation during global updates.
+    /// The edge value is approximated by the color gradient around each pixel,
+    /// and the connectivity measure is only driven by local updates.
+    /// Segmentation is still performed for the final evaluation.
+    /// Trades quality of the results for speed, meant for quick interactive iteration.
+    pub fast: bool,
+    /// Fraction of the peak each global reinforcement is capped at.
+    /// Smaller fractions let fewer pixels dominate a single step,
+    /// which slows down but stabilizes convergence.
+    pub clamp_fraction: f32,
+    /// Threshold of the regions the global updates are based on.
+    /// This only shapes the objectives guiding the optimization,
+    /// the output segmentations and their evaluation use a separate threshold.
+    /// Lower thresholds let weaker trails close regions early on.
+    pub update_threshold: f32,
+    /// Order of the segments of evaluated solutions and their outputs,
+    /// which determines the colors of `SegmentColoring::Index`.
+    pub segment_order: segments::SegmentOrder,
+    /// Pheromone deposited by each ant on the pixels it visited for the edge value.
+    /// Also used for the only channel of single objective optimization.
+    pub edge_deposit: f32,
+    /// Pheromone deposited by each ant on the pixels it visited for the connectivity measure.
+    /// The ratio to `edge_deposit` balances the influence of the objectives during walks.
+    pub connectivity_deposit: f32,
+    /// Pheromone every pixel of every channel starts with, so that the first ants
+    /// already follow and reinforce trails instead of walking only by color and target.
+    /// Global updates rescale the channels, see `normalize_sum`, so the floor shrinks
+    /// relative to the trails as they grow, and becomes negligible when normalizing the sum.
+    /// Unless `fast` is set, the connectivity channel of multi objective optimization
+    /// is raised by a baseline of its own after each global update, which outweighs small floors.
+    pub initial_pheromone: f32,
+    /// Reuse the regions of the previous global update,
+    /// only recomputing those touched by changes of the contour.
+    /// The resulting regions are the same, only their order may differ.
+    pub incremental: bool,
+    /// Ants running between global updates, instead of the default of the objective module.
+    /// More ants explore more of the image per step, but take proportionally longer.
+    pub ants_per_global_update: Option<usize>,
+    /// Let ants walk back to their spawn point after reaching their target,
+    /// see `AntColonyRules::ants_return`.
+    pub ants_return: bool,
+    /// Kernel used by `extract_edges` to find contours in the thresholded pheromones.
+    pub edge_kernel: [f32; 9],
+    /// Passes of `segments::refine_boundaries` applied to the segments of evaluated solutions,
+    /// and the segmentation outputs. Global updates use the unrefined regions.
+    pub refine_passes: usize,
+    /// Treat the image as a torus, whose opposite edges are connected.
+    /// Ants walk across the edges and segments continue on the opposite side,
+    /// so the segmentation of a seamless texture is seamless when tiled as well.
+    pub wrap: bool,
+    /// Let segments extend up to the edges of the image, see `ContourBorder::Open`.
+    /// Has no effect if `wrap` is set.
+    pub open_border: bool,
+    /// Color distances used for the objectives of evaluated solutions.
+    /// Global updates always use the manhattan distance.
+    pub objective_distances: ObjectiveDistances,
+    /// Objectives taking part, all others are still evaluated and reported.
+    /// Channels of inactive objectives are neither initialized nor updated, so they stay empty.
+    pub objectives: ObjectiveSelection,
+    /// Evaluate solutions by `segments::overall_entropy` as a fourth objective.
+    /// Global updates are not guided by it.
+    pub entropy_objective: bool,
+    /// Neighbourhoods of ant movement, objectives and regions.
+    pub connectivity: PipelineConnectivity,
+    /// Weights of the color channels, for images where some channels carry more signal.
+    /// If given, the objectives, global updates and ant movement all use
+    /// `color_distances::weighted_euclidean` instead of their usual distances.
+    pub channel_distance_weights: Option<[f64; 3]>,
+    /// Thresholds to choose from for the segmentation of each evaluated solution,
+    /// instead of a fixed threshold, see `pareto_pheromones::Evaluation::sweep`.
+    pub threshold_sweep: Option<ThresholdSweep>,
+    /// Number of segments to segment each evaluated solution into, by searching for the
+    /// threshold instead of using a fixed one, see `threshold_for_segments`.
+    /// Takes precedence over `threshold_sweep`.
+    pub target_segments: Option<TargetSegments>,
+}
+
+/// Number of segments to aim for, see `threshold_for_segments`.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetSegments {
+    pub count: usize,
+    /// Largest accepted difference to the count.
+    pub tolerance: usize,
+}
+
+/// Evenly spaced thresholds to try for each evaluated solution, including both ends.
+#[derive(Debug, Clone, Copy)]
+pub struct ThresholdSweep {
+    pub start: f32,
+    pub end: f32,
+    pub step: f32,
+}
+
+impl ThresholdSweep {
+    pub fn thresholds(&self) -> Vec<f32> {
+        // Multiples of the step do not accumulate rounding errors like repeated additions.
+        let count = ((self.end - self.start) / self.step + 1e-4).floor() as usize + 1;
+        return (0..count).map(|i| self.start + i as f32 * self.step).collect();
     }
-    return (segmented, segments);
+}
+
+/// Regions of the previous global update, kept for incremental segmentation.
+pub type SegmentHistory = Mutex<Option<segments::LabeledSegments>>;
+
+impl Default for SegmentationOptions {
+    fn default() -> Self {
+        return Self {
+            normalize_sum: false,
+            connectivity_weighting: segments::ConnectivityWeighting::Index,
+            edge_weighting: segments::EdgeWeighting::Isotropic,
+            fast: false,
+            clamp_fraction: 1.0 / 8.0,
+            update_threshold: 0.25,
+            segment_order: segments::SegmentOrder::Raster,
+            edge_deposit: 0.1,
+            initial_pheromone: 0.0,
+            connectivity_deposit: 0.01,
+            incremental: false,
+            ants_per_global_update: None,
+            ants_return: true,
+            edge_kernel: *image_arithmetic::LAPLACE_KERNEL,
+            refine_passes: 0,
+            wrap: false,
+            open_border: false,
+            objective_distances: ObjectiveDistances::default(),
+            objectives: ObjectiveSelection::default(),
+            channel_distance_weights: None,
+            entropy_objective: false,
+            connectivity: PipelineConnectivity::Legacy,
+            threshold_sweep: None,
+            target_segments: None,
+        };
+    }
+}
+
+impl SegmentationOptions {
+    /// Regions the global updates are based on.
+    /// In fast mode no regions are computed, so every neighbour contributes to the edge value.
+    /// In incremental mode the regions are updated from the given history.
+    pub fn update_regions<'a>(
+        &self, pheromones: &[PheromoneImage], history: &'a mut Option<segments::LabeledSegments>,
+    ) -> Cow<'a, Vec<HashSet<Point>>> {
+        if self.fast {
+            return Cow::Owned(vec![]);
+        }
+        if !self.incremental {
+            return Cow::Owned(
+                region_segmententation(
+                    pheromones,
+                    self.update_threshold,
+                    &self.edge_kernel,
+                    self.contour_border(),
+                    self.connectivity.regions(),
+                )
+                .1
+                .segments,
+            );
+        }
+        let contour = contour_segmententation(
+            pheromones,
+            self.update_threshold,
+            &self.edge_kernel,
+            self.contour_border(),
+        );
+        let labeled = match history.take() {
+            Some(mut labeled) => {
+                labeled.update(contour);
+                labeled
+            }
+            None => segments::LabeledSegments::new(contour, self.connectivity.regions(), self.wrap),
+        };
+        return Cow::Borrowed(&history.insert(labeled).segments);
+    }
+
+    /// Segments of the pheromones at the given threshold, with boundaries refined as configured,
+    /// in the configured order.
+    /// Evaluation and outputs of solutions are based on these segments.
+    pub fn region_segments(
+        &self, img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32,
+    ) -> Vec<HashSet<Point>> {
+        let mut segments = cached_region_segmententation(
+            pheromones,
+            threshold,
+            &self.edge_kernel,
+            self.contour_border(),
+            self.connectivity.regions(),
+        )
+        .1
+        .segments;
+        let dist = &color_distances::euclidean;
+        segments::refine_boundaries(img, &mut segments, dist, self.refine_passes);
+        segments::sort_segments(&mut segments, self.segment_order);
+        return segments;
+    }
+
+    /// Contour of the segments of `region_segments`, so that it agrees with their colorization.
+    /// Without refinement, this is the contour of the pheromones.
+    pub fn region_contour(
+        &self, img: &RgbImage, pheromones: &[PheromoneImage], threshold: f32,
+    ) -> RgbImage {
+        if self.refine_passes == 0 {
+            return contour_segmententation(
+                pheromones,
+                threshold,
+                &self.edge_kernel,
+                self.contour_border(),
+            );
+        }
+        let segments = self.region_segments(img, pheromones, threshold);
+        return segments_contour(&segments, img.width(), img.height(), self.wrap);
+    }
+
+    /// The given distance, unless it is replaced by the channel-weighted distance.
+    pub fn color_distance(&self, default: &'static ColorSpaceDistance) -> Box<ColorSpaceDistance> {
+        if let Some(weights) = self.channel_distance_weights {
+            return Box::new(move |a, b| color_distances::weighted_euclidean(a, b, &weights));
+        }
+        return Box::new(default);
+    }
+
+    pub fn contour_border(&self) -> ContourBorder {
+        if self.wrap {
+            return ContourBorder::Wrapped;
+        }
+        if self.open_border {
+            return ContourBorder::Open;
+        }
+        return ContourBorder::Closed;
+    }
+
+    pub fn normalize(&self, pheromone: &mut PheromoneImage) {
+        if self.normalize_sum {
+            pheromone.normalize_sum();
+        } else {
+            pheromone.normalize();
+        }
+    }
+
+    /// Same as `normalize` followed by `mul_scalar`, but with one pass less.
+    pub fn normalize_scaled(&self, pheromone: &mut PheromoneImage, num: f32) {
+        if self.normalize_sum {
+            pheromone.normalize_sum_scaled(num);
+        } else {
+            pheromone.normalize_scaled(num);
+        }
+    }
+}
+
+/// Names of the pheromone channels used by the rules.
+pub fn channel_labels(multi: bool) -> Vec<&'static str> {
+    if multi {
+        return multi_objective::channel_labels();
+    }
+    return single_objective::channel_labels();
 }
 
 pub fn create_rules<R: rand::Rng + 'static>(
-    img: &RgbImage, parallelity: Option<usize>, multi: bool,
+    img: &RgbImage, parallelity: Option<usize>, multi: bool, options: SegmentationOptions,
 ) -> AntColonyRules<R> {
     let max_steps = ((img.width() * img.height()) / 8) as usize;
-    let ants_return = true;
-    if multi {
-        return AntColonyRules::new(
+    let ants_return = options.ants_return;
+    let history = SegmentHistory::default();
+    let mut rules = if multi {
+        AntColonyRules::new(
             max_steps,
-            multi_objective::ants_per_global_update(),
+            options.ants_per_global_update.unwrap_or(multi_objective::ants_per_global_update()),
             ants_return,
             parallelity,
             vec![
-                multi_objective::initialization_functions(),
-                multi_objective::local_update_functions(),
+                multi_objective::initialization_functions(&options),
+                multi_objective::local_update_functions(&options),
             ],
-            Some(Box::new(multi_objective::global)),
+            Some(Box::new(move |rng, img, pheromones, visited| {
+                multi_objective::global(&options, &history, rng, img, pheromones, visited)
+            })),
         )
-        .unwrap();
+        .unwrap()
     } else {
-        return AntColonyRules::new(
+        AntColonyRules::new(
             max_steps,
-            single_objective::ants_per_global_update(),
+            options.ants_per_global_update.unwrap_or(single_objective::ants_per_global_update()),
             ants_return,
             parallelity,
             vec![
-                single_objective::initialization_functions(),
-                single_objective::local_update_functions(),
+                single_objective::initialization_functions(&options),
+                single_objective::local_update_functions(&options),
             ],
-            Some(Box::new(single_objective::global)),
+            Some(Box::new(move |rng, img, pheromones, visited| {
+                single_objective::global(&options, &history, rng, img, pheromones, visited)
+            })),
         )
-        .unwrap();
+        .unwrap()
+    };
+    rules.wrap = options.wrap;
+    rules.connectivity = options.connectivity.neighbourhood();
+    rules.channel_distance_weights = options.channel_distance_weights;
+    return rules;
+}
+
+/// Initialization raising all pixels of a channel to the `initial_pheromone` floor,
+/// none if there is no floor.
+fn pheromone_floor<R: rand::Rng + 'static>(
+    options: &SegmentationOptions,
+) -> Option<Box<UpdateFunction<R>>> {
+    let amount = options.initial_pheromone;
+    if amount <= 0.0 {
+        return None;
     }
+    return Some(Box::new(move |_rng, _img, pheromone, _visited| pheromone.add_scalar(amount)));
 }
 
 pub fn increase_phermomone<I, P>(pheromone: &mut PheromoneImage, points: I, amount: f32)
@@ -112,11 +779,72 @@ where
     }
 }
 
-pub fn extract_edges(pheromone: &PheromoneImage, threshold: f32) -> PheromoneImage {
+/// Color gradient magnitude of each pixel, i.e. the color distance to all of its neighbours.
+pub fn saliency_map(img: &RgbImage, connectivity: Connectivity, wrap: bool) -> PheromoneImage {
+    return PheromoneImage::from_fn(img.width(), img.height(), |x, y| {
+        let point = Point::from((x, y));
+        let dist = &color_distances::manhattan;
+        [segments::local_edge_value(img, &vec![], dist, &point, connectivity, wrap) as f32].into()
+    });
+}
+
+/// Applies the kernel to the image.
+/// The outermost pixels are left empty for closed borders,
+/// otherwise the image is padded according to the border before applying the kernel.
+pub fn filter_with_border(
+    pheromone: &PheromoneImage, kernel: &[f32; 9], border: ContourBorder,
+) -> PheromoneImage {
+    let (w, h) = pheromone.dimensions();
+    let padded = match border {
+        ContourBorder::Closed => return imageops::filter3x3(pheromone, kernel),
+        ContourBorder::Open => PheromoneImage::from_fn(w + 2, h + 2, |x, y| {
+            *pheromone.get_pixel(x.saturating_sub(1).min(w - 1), y.saturating_sub(1).min(h - 1))
+        }),
+        ContourBorder::Wrapped => PheromoneImage::from_fn(w + 2, h + 2, |x, y| {
+            *pheromone.get_pixel((x + w - 1) % w, (y + h - 1) % h)
+        }),
+    };
+    return imageops::crop_imm(&imageops::filter3x3(&padded, kernel), 1, 1, w, h).to_image();
+}
+
+/// Contours of the pheromone thresholded at the given value, see `filter_with_border`.
+pub fn extract_edges(
+    pheromone: &PheromoneImage, threshold: f32, kernel: &[f32; 9], border: ContourBorder,
+) -> PheromoneImage {
     let mut result = pheromone.clone();
     result.binarize(threshold);
     imageops::invert(&mut result);
-    return imageops::filter3x3(&result, image_arithmetic::LAPLACE_KERNEL);
+    return filter_with_border(&result, kernel, border);
+}
+
+/// Magnitude of the kernel's response to the sum of all channels, before any thresholding,
+/// for external code to decide on contours with full precision.
+/// Unlike `filter_with_border`, responses are not clamped to `[0, 1]`.
+/// Pixels beyond the edges repeat the outermost ones, unless the border is wrapped.
+/// Note that `contour_segmententation` thresholds the sum before applying the kernel,
+/// so thresholding this image does not reproduce its contours exactly.
+pub fn edge_strength(
+    pheromones: &[PheromoneImage], kernel: &[f32; 9], border: ContourBorder,
+) -> PheromoneImage {
+    let sum = sum_channels(pheromones);
+    let (w, h) = sum.dimensions();
+    let wrap = border == ContourBorder::Wrapped;
+    return PheromoneImage::from_fn(w, h, |x, y| {
+        let center = Point::from((x, y));
+        let response: f32 = kernel
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let neighbour = center + Point { x: i as i64 % 3 - 1, y: i as i64 / 3 - 1 };
+                let neighbour = neighbour.within(w, h, wrap).unwrap_or(Point {
+                    x: neighbour.x.clamp(0, w as i64 - 1),
+                    y: neighbour.y.clamp(0, h as i64 - 1),
+                });
+                return weight * neighbour.get_pixel(&sum).0[0];
+            })
+            .sum();
+        return Luma([response.abs()]);
+    });
 }
 
 /// Combines the ant colony primitives with concrete rules
@@ -125,15 +853,17 @@ pub mod multi_objective {
     use super::*;
 
     pub fn local_edge_value<R: rand::Rng + 'static>(
-        _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage, _visited: &HashSet<Point>,
+        amount: f32, _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage,
+        _visited: &HashSet<Point>,
     ) {
-        increase_phermomone(_pheromone, _visited, 0.1);
+        increase_phermomone(_pheromone, _visited, amount);
     }
 
     pub fn local_connectivity_measure<R: rand::Rng + 'static>(
-        _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage, _visited: &HashSet<Point>,
+        amount: f32, _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage,
+        _visited: &HashSet<Point>,
     ) {
-        increase_phermomone(_pheromone, _visited, 0.01);
+        increase_phermomone(_pheromone, _visited, amount);
     }
 
     // pub fn local_overall_deviation<R: rand::Rng + 'static>(
@@ -142,57 +872,88 @@ pub mod multi_objective {
     // }
 
     pub fn global<R: rand::Rng + 'static>(
-        _rng: &mut R, _img: &RgbImage, _pheromones: &mut [PheromoneImage],
-        _visited: &HashSet<Point>,
+        options: &SegmentationOptions, history: &SegmentHistory, _rng: &mut R, _img: &RgbImage,
+        _pheromones: &mut [PheromoneImage], _visited: &HashSet<Point>,
     ) {
-        let (_, regions) = region_segmententation(_pheromones, 0.25);
+        let mut history = history.lock().unwrap();
+        let regions = options.update_regions(_pheromones, &mut history);
         let (edges, rest) = _pheromones.split_first_mut().unwrap();
         let (connectivity, _) = rest.split_first_mut().unwrap();
         // let (deviation, _) = rest.split_first_mut().unwrap();
 
         // Edge Value.
-        let mut increase = edges.clone();
-        for point in _visited {
-            point.get_pixel_mut(&mut increase).apply(|_| {
-                segments::local_edge_value(_img, &regions, &color_distances::manhattan, point)
-                    as f32
-            });
+        if options.objectives.edge_value {
+            let dist = options.color_distance(&color_distances::manhattan);
+            let mut increase = edges.clone();
+            for point in _visited {
+                point.get_pixel_mut(&mut increase).apply(|_| {
+                    let weighting = options.edge_weighting;
+                    let neighbourhood = options.connectivity.neighbourhood();
+                    weighting.local_value(_img, &regions, &dist, point, neighbourhood, options.wrap)
+                        as f32
+                });
+            }
+            increase.clamp_normalize(options.clamp_fraction);
+            edges.add(&increase);
+            options.normalize_scaled(edges, 5.0);
         }
-        increase.clamp(increase.max() / 8.0);
-        increase.normalize();
-        edges.add(&increase);
-        edges.normalize();
-        edges.mul_scalar(5.0);
 
         // Connectivity Measure.
-        increase = connectivity.clone();
-        for point in _visited {
-            point
-                .get_pixel_mut(&mut increase)
-                .apply(|_| segments::local_connectivity_measure(_img, &regions, point) as f32);
+        // Inactive objectives keep their channel, which stays empty.
+        if !options.objectives.connectivity_measure {
+            return;
         }
-        increase.clamp(increase.max() / 8.0);
-        increase.normalize();
-        increase.mul_scalar(-1.0);
-        connectivity.add(&increase);
-        connectivity.add_scalar(1.0);
-        connectivity.normalize();
-        connectivity.mul_scalar(2.0);
+        if !options.fast {
+            let mut increase = connectivity.clone();
+            for point in _visited {
+                point.get_pixel_mut(&mut increase).apply(|_| {
+                    let weighting = options.connectivity_weighting;
+                    let neighbourhood = options.connectivity.neighbourhood();
+                    weighting.local_measure(_img, &regions, point, neighbourhood, options.wrap)
+                        as f32
+                });
+            }
+            increase.clamp_normalize(options.clamp_fraction);
+            connectivity.add_scaled(&increase, -1.0, 1.0);
+        }
+        options.normalize_scaled(connectivity, 2.0);
     }
 
-    pub fn initialization_functions<R: rand::Rng + 'static>() -> Vec<Option<Box<UpdateFunction<R>>>>
-    {
-        return vec![None, None];
+    pub fn initialization_functions<R: rand::Rng + 'static>(
+        options: &SegmentationOptions,
+    ) -> Vec<Option<Box<UpdateFunction<R>>>> {
+        let active = options.objectives;
+        return vec![
+            pheromone_floor(options).filter(|_| active.edge_value),
+            pheromone_floor(options).filter(|_| active.connectivity_measure),
+        ];
     }
 
-    pub fn local_update_functions<R: rand::Rng + 'static>() -> Vec<Option<Box<UpdateFunction<R>>>> {
+    pub fn local_update_functions<R: rand::Rng + 'static>(
+        options: &SegmentationOptions,
+    ) -> Vec<Option<Box<UpdateFunction<R>>>> {
+        let edge_deposit = options.edge_deposit;
+        let connectivity_deposit = options.connectivity_deposit;
+        let active = options.objectives;
         return vec![
-            Some(Box::new(local_edge_value)),
-            Some(Box::new(local_connectivity_measure)),
+            active.edge_value.then(|| -> Box<UpdateFunction<R>> {
+                Box::new(move |rng, img, pheromone, visited| {
+                    local_edge_value(edge_deposit, rng, img, pheromone, visited)
+                })
+            }),
+            active.connectivity_measure.then(|| -> Box<UpdateFunction<R>> {
+                Box::new(move |rng, img, pheromone, visited| {
+                    local_connectivity_measure(connectivity_deposit, rng, img, pheromone, visited)
+                })
+            }),
             // Some(Box::new(local_overall_deviation)),
         ];
     }
 
+    pub fn channel_labels() -> Vec<&'static str> {
+        return vec!["edge value", "connectivity"];
+    }
+
     pub fn ants_per_global_update() -> usize {
         return 40;
     }
@@ -203,59 +964,150 @@ pub mod multi_objective {
 pub mod single_objective {
     use super::*;
 
-    pub fn initialization_functions<R: rand::Rng + 'static>() -> Vec<Option<Box<UpdateFunction<R>>>>
-    {
-        return vec![None];
+    pub fn initialization_functions<R: rand::Rng + 'static>(
+        options: &SegmentationOptions,
+    ) -> Vec<Option<Box<UpdateFunction<R>>>> {
+        return vec![pheromone_floor(options)];
     }
 
     pub fn local<R: rand::Rng + 'static>(
-        _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage, _visited: &HashSet<Point>,
+        amount: f32, _rng: &mut R, _img: &RgbImage, _pheromone: &mut PheromoneImage,
+        _visited: &HashSet<Point>,
     ) {
-        increase_phermomone(_pheromone, _visited, 0.1);
+        increase_phermomone(_pheromone, _visited, amount);
     }
 
     pub fn global<R: rand::Rng + 'static>(
-        _rng: &mut R, _img: &RgbImage, _pheromones: &mut [PheromoneImage],
-        _visited: &HashSet<Point>,
+        options: &SegmentationOptions, history: &SegmentHistory, _rng: &mut R, _img: &RgbImage,
+        _pheromones: &mut [PheromoneImage], _visited: &HashSet<Point>,
     ) {
         let common_pheromone = &mut _pheromones[0];
-        let (_, regions) = region_segmententation(std::slice::from_ref(common_pheromone), 0.25);
+        let mut history = history.lock().unwrap();
+        let regions = options.update_regions(std::slice::from_ref(common_pheromone), &mut history);
         let mut increase = common_pheromone.clone();
         // Edge Value.
+        let dist = options.color_distance(&color_distances::manhattan);
         for point in _visited {
             point.get_pixel_mut(&mut increase).apply(|_| {
-                segments::local_edge_value(_img, &regions, &color_distances::manhattan, point)
+                let weighting = options.edge_weighting;
+                let neighbourhood = options.connectivity.neighbourhood();
+                weighting.local_value(_img, &regions, &dist, point, neighbourhood, options.wrap)
                     as f32
             });
         }
-        increase.clamp(increase.max() / 8.0);
-        increase.normalize();
+        increase.clamp_normalize(options.clamp_fraction);
         common_pheromone.add(&increase);
+        if options.fast {
+            options.normalize(common_pheromone);
+            return;
+        }
         // Connectivity Measure.
         increase = common_pheromone.clone();
         for point in _visited {
-            point
-                .get_pixel_mut(&mut increase)
-                .apply(|_| segments::local_connectivity_measure(_img, &regions, point) as f32);
+            point.get_pixel_mut(&mut increase).apply(|_| {
+                let weighting = options.connectivity_weighting;
+                let neighbourhood = options.connectivity.neighbourhood();
+                weighting.local_measure(_img, &regions, point, neighbourhood, options.wrap) as f32
+            });
         }
-        increase.clamp(increase.max() / 8.0);
-        increase.normalize();
+        increase.clamp_normalize(options.clamp_fraction);
         // // Let connectivity become more important as edges start to from.
         // let mut weight = segments::edge_value(_img, &regions, &color_distances::cosine) as f32;
         // weight /= 2.0 * _img.len() as f32;
         // weight += 0.5;
         // // Slows down computation quite a bit. Not worth it.
-        increase.mul_scalar(-1.0);
-        common_pheromone.add(&increase);
-        common_pheromone.add_scalar(1.0);
-        common_pheromone.normalize();
+        common_pheromone.add_scaled(&increase, -1.0, 1.0);
+        options.normalize(common_pheromone);
+    }
+
+    pub fn local_update_functions<R: rand::Rng + 'static>(
+        options: &SegmentationOptions,
+    ) -> Vec<Option<Box<UpdateFunction<R>>>> {
+        let deposit = options.edge_deposit;
+        return vec![Some(Box::new(move |rng, img, pheromone, visited| {
+            local(deposit, rng, img, pheromone, visited)
+        }))];
     }
 
-    pub fn local_update_functions<R: rand::Rng + 'static>() -> Vec<Option<Box<UpdateFunction<R>>>> {
-        return vec![Some(Box::new(local))];
+    pub fn channel_labels() -> Vec<&'static str> {
+        return vec!["weighted sum"];
     }
 
     pub fn ants_per_global_update() -> usize {
         return 40;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pheromones with a closed square trail, segmented into its inside and outside.
+    fn square_trail(width: u32, height: u32) -> Vec<PheromoneImage> {
+        let pheromone = PheromoneImage::from_fn(width, height, |x, y| {
+            let on_trail = (x == 3 || x == 8) && (3..=8).contains(&y)
+                || (y == 3 || y == 8) && (3..=8).contains(&x);
+            return Luma([if on_trail { 1.0 } else { 0.0 }]);
+        });
+        return vec![pheromone];
+    }
+
+    /// Pheromones with two vertical trails, which split the image into three regions,
+    /// unless the outer ones are joined across the edges.
+    fn vertical_trails(width: u32, height: u32) -> Vec<PheromoneImage> {
+        let pheromone = PheromoneImage::from_fn(width, height, |x, _| {
+            return Luma([if x == 4 || x == 10 { 1.0 } else { 0.0 }]);
+        });
+        return vec![pheromone];
+    }
+
+    #[test]
+    fn wrapped_segment_crosses_image_edge() {
+        let (width, height) = (16, 9);
+        let img = RgbImage::new(width, height);
+        let pheromones = vertical_trails(width, height);
+        let mut options = SegmentationOptions::default();
+        assert_eq!(options.region_segments(&img, &pheromones, 0.5).len(), 3);
+        options.wrap = true;
+        let segments = options.region_segments(&img, &pheromones, 0.5);
+        assert_eq!(segments.len(), 2);
+        let left = Point { x: 0, y: 4 };
+        let right = Point { x: width as i64 - 1, y: 4 };
+        assert!(segments.iter().any(|segment| segment.contains(&left) && segment.contains(&right)));
+    }
+
+    fn cached_segments(pheromones: &[PheromoneImage]) -> Vec<HashSet<Point>> {
+        let kernel = image_arithmetic::LAPLACE_KERNEL;
+        let border = ContourBorder::Closed;
+        let connectivity = CONTOUR_CONNECTIVITY.complement();
+        let (_, segmentation) =
+            cached_region_segmententation(pheromones, 0.5, kernel, border, connectivity);
+        return segmentation.segments;
+    }
+
+    // The cache and its statistics are shared by all tests, which may evict entries at any time,
+    // so this checks the keys results are cached by instead of counting hits.
+    #[test]
+    fn region_cache_hits_identical_and_misses_mutated_pheromones() {
+        let (kernel, border) = (image_arithmetic::LAPLACE_KERNEL, ContourBorder::Closed);
+        let connectivity = CONTOUR_CONNECTIVITY.complement();
+        let key = |pheromones: &[PheromoneImage]| {
+            segmentation_key(pheromones, 0.5, kernel, border, connectivity)
+        };
+        let mut pheromones = square_trail(13, 17);
+        let first = cached_segments(&pheromones);
+        assert_eq!(first.len(), 2);
+        assert_eq!(key(&pheromones), key(&pheromones.clone()));
+        assert_eq!(cached_segments(&pheromones), first);
+
+        let unchanged = key(&pheromones);
+        pheromones[0].put_pixel(8, 5, Luma([0.0]));
+        assert_ne!(key(&pheromones), unchanged);
+        assert_ne!(
+            segmentation_key(&pheromones, 0.25, kernel, border, connectivity),
+            key(&pheromones)
+        );
+        // The gap in the trail joins the inside and outside of the square.
+        assert_eq!(cached_segments(&pheromones).len(), 1);
+    }
+}
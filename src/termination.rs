@@ -0,0 +1,175 @@
+//! Criteria deciding when to stop generating new solutions.
+
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use super::pareto_pheromones::ParetoPheromones;
+
+use log::info;
+use pareto_front::ParetoFront;
+
+pub trait TerminationCriterion {
+    /// Consulted after each iteration, i.e. after each full run of the colony.
+    fn should_stop(
+        &self, elapsed: Duration, iterations: usize, front: &ParetoFront<ParetoPheromones>,
+    ) -> bool;
+}
+
+/// Stops once the given time has passed.
+/// The current iteration is always finished, so this is a soft timeout.
+pub struct Timeout(pub Duration);
+
+impl TerminationCriterion for Timeout {
+    fn should_stop(
+        &self, elapsed: Duration, _iterations: usize, _front: &ParetoFront<ParetoPheromones>,
+    ) -> bool {
+        if elapsed < self.0 {
+            return false;
+        }
+        info!("Timeout of {:?} reached after {:?}.", self.0, elapsed);
+        return true;
+    }
+}
+
+/// Stops after the given amount of iterations.
+pub struct MaxIterations(pub usize);
+
+impl TerminationCriterion for MaxIterations {
+    fn should_stop(
+        &self, _elapsed: Duration, iterations: usize, _front: &ParetoFront<ParetoPheromones>,
+    ) -> bool {
+        return iterations >= self.0;
+    }
+}
+
+/// Stops once the pareto front has not changed for the given amount of iterations.
+pub struct Convergence {
+    patience: usize,
+    last_front: Cell<u64>,
+    unchanged: Cell<usize>,
+}
+
+impl Convergence {
+    pub fn new(patience: usize) -> Self {
+        return Self { patience, last_front: Cell::new(0), unchanged: Cell::new(0) };
+    }
+
+    fn front_hash(front: &ParetoFront<ParetoPheromones>) -> u64 {
+        let mut fingerprints: Vec<_> = front.iter().map(|s| s.fingerprint).collect();
+        fingerprints.sort();
+        let mut hasher = DefaultHasher::new();
+        fingerprints.hash(&mut hasher);
+        return hasher.finish();
+    }
+}
+
+impl TerminationCriterion for Convergence {
+    fn should_stop(
+        &self, _elapsed: Duration, _iterations: usize, front: &ParetoFront<ParetoPheromones>,
+    ) -> bool {
+        let hash = Self::front_hash(front);
+        if hash == self.last_front.get() {
+            self.unchanged.set(self.unchanged.get() + 1);
+        } else {
+            self.last_front.set(hash);
+            self.unchanged.set(0);
+        }
+        if self.unchanged.get() < self.patience {
+            return false;
+        }
+        info!("Pareto front converged, unchanged for {} iterations.", self.unchanged.get());
+        return true;
+    }
+}
+
+/// Stops as soon as any of the criteria is met.
+/// Every criterion is consulted each time, so stateful criteria stay up to date.
+pub struct AnyOf(pub Vec<Box<dyn TerminationCriterion>>);
+
+impl TerminationCriterion for AnyOf {
+    fn should_stop(
+        &self, elapsed: Duration, iterations: usize, front: &ParetoFront<ParetoPheromones>,
+    ) -> bool {
+        let mut stop = false;
+        for criterion in &self.0 {
+            stop |= criterion.should_stop(elapsed, iterations, front);
+        }
+        return stop;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment_generation::ObjectiveSelection;
+    use std::rc::Rc;
+
+    fn front_of(fingerprint: u64) -> ParetoFront<ParetoPheromones> {
+        let mut front = ParetoFront::new();
+        front.push(ParetoPheromones {
+            pheromones: vec![],
+            threshold: 0.5,
+            segments: vec![],
+            segment_count: 0,
+            edge_value: 0.0,
+            connectivity_measure: 0.0,
+            overall_deviation: 0.0,
+            overall_entropy: None,
+            active_objectives: ObjectiveSelection::default(),
+            fingerprint,
+        });
+        return front;
+    }
+
+    /// Counts how often it is consulted, but never stops.
+    struct Counting(Rc<Cell<usize>>);
+
+    impl TerminationCriterion for Counting {
+        fn should_stop(
+            &self, _elapsed: Duration, _iterations: usize, _front: &ParetoFront<ParetoPheromones>,
+        ) -> bool {
+            self.0.set(self.0.get() + 1);
+            return false;
+        }
+    }
+
+    #[test]
+    fn convergence_stops_after_patience_unchanged_fronts() {
+        let convergence = Convergence::new(2);
+        let (first, second) = (front_of(1), front_of(2));
+        let stops = |front| convergence.should_stop(Duration::ZERO, 0, front);
+        assert!(!stops(&first));
+        assert!(!stops(&first));
+        assert!(stops(&first));
+        // A changed front starts the count anew.
+        assert!(!stops(&second));
+        assert!(!stops(&second));
+        assert!(stops(&second));
+    }
+
+    #[test]
+    fn any_of_consults_criteria_after_one_stops() {
+        let calls = Rc::new(Cell::new(0));
+        let any_of = AnyOf(vec![
+            Box::new(MaxIterations(1)),
+            Box::new(Counting(calls.clone())),
+        ]);
+        let front = front_of(1);
+        assert!(!any_of.should_stop(Duration::ZERO, 0, &front));
+        assert!(any_of.should_stop(Duration::ZERO, 1, &front));
+        assert!(any_of.should_stop(Duration::ZERO, 2, &front));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn timeout_and_max_iterations() {
+        let front = ParetoFront::new();
+        let timeout = Timeout(Duration::from_secs(2));
+        assert!(!timeout.should_stop(Duration::from_secs(1), 0, &front));
+        assert!(timeout.should_stop(Duration::from_secs(2), 0, &front));
+        assert!(!MaxIterations(3).should_stop(Duration::ZERO, 2, &front));
+        assert!(MaxIterations(3).should_stop(Duration::ZERO, 3, &front));
+    }
+}